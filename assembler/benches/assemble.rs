@@ -0,0 +1,56 @@
+// assembles a synthetic 100k-line program, the kind of output the macro processor produces.
+// SMAC0 only has 1000 words, so the program is made of blocks that each ORIGIN back to the
+// start of memory while every label stays unique. run with `cargo bench`
+
+use std::time::{Duration, Instant};
+
+use assembler::Assembler;
+
+const LINES: usize = 100_000;
+const BLOCK: usize = 50;
+const RUNS: u32 = 5;
+
+fn synthetic_program() -> Vec<String> {
+    let mut lines = vec![String::from("START 0")];
+    let blocks = LINES / BLOCK;
+    for block in 0..blocks {
+        lines.push(String::from("ORIGIN 0"));
+        lines.push(format!("K{block} EQU {}", block % 500));
+        for i in 0..BLOCK - 4 {
+            let line = match i % 5 {
+                0 => format!("B{block}_{i}: MOVER AREG V{block}"),
+                1 => format!("ADD AREG K{block}"),
+                2 => format!("SUB BREG ='{}'", i % 7),
+                // backward into the previous block, forward into the next one
+                3 => format!("BC LT B{}_0", block.saturating_sub(1)),
+                _ => format!("BC ANY B{}_0", (block + 1) % blocks),
+            };
+            lines.push(line);
+        }
+        lines.push(format!("V{block}: DC {block}"));
+        lines.push(String::from("LTORG"));
+    }
+    lines.push(String::from("END"));
+    lines
+}
+
+fn main() {
+    let source_lines = synthetic_program();
+    let mut total = Duration::ZERO;
+
+    for _ in 0..RUNS {
+        let started = Instant::now();
+        let output = Assembler::new().assemble(&source_lines);
+        total += started.elapsed();
+
+        assert!(output.error_table.is_empty(), "{}", output.error_table[0]);
+        // K, V and every fifth line labelled in each block
+        assert_eq!(output.symbol_table.len(), LINES / BLOCK * (2 + (BLOCK - 4).div_ceil(5)));
+    }
+
+    println!(
+        "assembled {} lines in {:?} on average over {RUNS} runs",
+        source_lines.len(),
+        total / RUNS
+    );
+}
@@ -0,0 +1,148 @@
+// conditional assembly. `IF expr` or `IF expr EQ expr` (also NE, LT, LE, GT, GE) assembles the
+// lines up to the matching ELSE or ENDIF only when the condition holds, a lone expression holds when
+// it is not 0. conditions are evaluated in pass 1, so their symbols have to be EQU or SET symbols
+// defined earlier, or `-D NAME=VALUE` defines.
+//
+// `NAME SET expr` is an assembly-time variable: unlike EQU it can be given a new value later on,
+// and operands that use it take the value it has at that line
+
+use std::slice::Iter;
+
+use crate::tokenizer::Token;
+use crate::{Assembler, ErrorType, Severity, SourceLocation};
+
+// an IF whose ENDIF has not been seen yet
+pub(crate) struct Conditional {
+    // lines in the current branch are assembled
+    active: bool,
+    // false inside a skipped block, then neither branch is assembled
+    enclosing_active: bool,
+    seen_else: bool,
+    location: SourceLocation,
+}
+
+impl Assembler {
+    pub(crate) fn assembling(&self) -> bool {
+        self.conditionals.last().is_none_or(|conditional| conditional.active)
+    }
+
+    // IF, ELSE and ENDIF are followed in skipped blocks too, so nesting stays balanced. returns whether
+    // the lines around the block are assembled, that is where a label on the directive belongs
+    pub(crate) fn process_conditional(&mut self, directive: &Token, tokens: &mut Iter<Token>) -> bool {
+        match directive.text.to_ascii_uppercase().as_str() {
+            "IF" => {
+                let enclosing_active = self.assembling();
+                // a skipped block may refer to symbols that were never defined, don't evaluate it
+                let holds = enclosing_active && self.condition(tokens).unwrap_or(false);
+                self.conditionals.push(Conditional {
+                    active: holds,
+                    enclosing_active,
+                    seen_else: false,
+                    location: self.location(directive.columns.clone()),
+                });
+                enclosing_active
+            }
+            "ELSE" => {
+                self.check_extra_operands(tokens);
+                match self.conditionals.last_mut() {
+                    Some(conditional) if !conditional.seen_else => {
+                        conditional.active = conditional.enclosing_active && !conditional.active;
+                        conditional.seen_else = true;
+                        conditional.enclosing_active
+                    }
+                    _ => {
+                        self.report(directive.columns.clone(), ErrorType::UnmatchedConditional(String::from("ELSE")));
+                        self.assembling()
+                    }
+                }
+            }
+            _ => {
+                self.check_extra_operands(tokens);
+                if self.conditionals.pop().is_none() {
+                    self.report(directive.columns.clone(), ErrorType::UnmatchedConditional(String::from("ENDIF")));
+                }
+                self.assembling()
+            }
+        }
+    }
+
+    fn condition(&mut self, tokens: &mut Iter<Token>) -> Option<bool> {
+        let Some(left) = tokens.next() else {
+            self.report(self.end_of_line(), ErrorType::InvalidOperand);
+            return None;
+        };
+        let (left, _) = self.absolute_expression(left)?;
+        let Some(comparison) = tokens.next() else {
+            return Some(left != 0);
+        };
+        let Some(right) = tokens.next() else {
+            self.report(self.end_of_line(), ErrorType::InvalidOperand);
+            return None;
+        };
+        let (right, _) = self.absolute_expression(right)?;
+        self.check_extra_operands(tokens);
+
+        match comparison.text.to_ascii_uppercase().as_str() {
+            "EQ" => Some(left == right),
+            "NE" => Some(left != right),
+            "LT" => Some(left < right),
+            "LE" => Some(left <= right),
+            "GT" => Some(left > right),
+            "GE" => Some(left >= right),
+            _ => {
+                self.report(comparison.columns.clone(), ErrorType::InvalidComparison);
+                None
+            }
+        }
+    }
+
+    pub(crate) fn process_set(&mut self, label: Option<&Token>, tokens: &mut Iter<Token>) -> Option<usize> {
+        let Some(label) = label else {
+            self.report(self.whole_line(), ErrorType::MissingSymbolName);
+            return None;
+        };
+        let Some(expr) = tokens.next() else {
+            self.report(self.end_of_line(), ErrorType::InvalidOperand);
+            return None;
+        };
+        self.check_extra_operands(tokens);
+        let value = match self.absolute_expression(expr)? {
+            (value, _) if value < 0 => {
+                self.report(expr.columns.clone(), ErrorType::NegativeValue);
+                return None;
+            }
+            (value, _) => value as usize,
+        };
+
+        match self.find_symbol(label.text) {
+            Some(index) if self.symbol_table[index].variable => self.symbol_table[index].address = value,
+            _ => {
+                // a name used before its first SET would be backpatched with whatever value it ends up with
+                let earlier_use = self
+                    .find_symbol(label.text)
+                    .map(|index| &self.symbol_table[index])
+                    .filter(|symbol| symbol.used && !symbol.defined && !symbol.external)
+                    .map(|symbol| symbol.first_use.clone());
+                if let Some(first_use) = earlier_use {
+                    let location = first_use.unwrap_or_else(|| self.location(label.columns.clone()));
+                    self.report_at(Severity::Error, ErrorType::ForwardReference(label.text.to_string()), location);
+                }
+                // a first SET defines the variable, anything else already using the name is a duplicate
+                self.define_symbol(label, value, false);
+                if let Some(index) = self.find_symbol(label.text) {
+                    let symbol = &mut self.symbol_table[index];
+                    if symbol.definition.as_ref().is_some_and(|d| d.line_number == self.line_number) {
+                        symbol.variable = true;
+                    }
+                }
+            }
+        }
+        Some(value)
+    }
+
+    pub(crate) fn check_unterminated_conditionals(&mut self) {
+        for conditional in std::mem::take(&mut self.conditionals) {
+            self.report_at(Severity::Error, ErrorType::UnterminatedIf, conditional.location);
+        }
+    }
+}
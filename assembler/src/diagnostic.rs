@@ -0,0 +1,198 @@
+use std::fmt;
+use std::ops::Range;
+
+use serde::Serialize;
+
+use crate::{WORD_MAX, WORD_MIN};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+// serialized as `{"code": "undefined_symbol", "argument": "NUM"}`, unit variants have no argument
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "code", content = "argument")]
+pub enum ErrorType {
+    InvalidValue,
+    UnknownMnemonic,
+    InvalidOperand,
+    MissingLabel,
+    MissingConditionCode,
+    UnknownConditionCode(String),
+    MissingOperand,
+    TooManyOperands,
+    RegisterWhereMemoryExpected(String),
+    ImmediateNotAllowed,
+    ImmediateOutOfRange(usize),
+    UndefinedSymbol(String),
+    InvalidNumber,
+    NegativeValue,
+    WordOutOfRange(isize),
+    AddressOutOfRange(usize),
+    AddressInUse(usize),
+    DuplicateLabel(String),
+    UnusedSymbol(String),
+    InvalidLiteral,
+    InvalidExpression,
+    ForwardReference(String),
+    MissingSymbolName,
+    ExternalDefinedLocally(String),
+    ExternalEntry(String),
+    InvalidComparison,
+    UnmatchedConditional(String),
+    UnterminatedIf,
+    InvalidConstant,
+}
+
+impl fmt::Display for ErrorType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorType::InvalidValue => write!(f, "invalid register"),
+            ErrorType::UnknownMnemonic => write!(f, "unknown mnemonic"),
+            ErrorType::InvalidOperand => write!(f, "invalid or missing operand"),
+            ErrorType::MissingLabel => write!(f, "missing branch target"),
+            ErrorType::MissingConditionCode => write!(f, "missing condition code"),
+            ErrorType::UnknownConditionCode(name) => write!(f, "unknown condition code `{name}`"),
+            ErrorType::MissingOperand => write!(f, "missing operand"),
+            ErrorType::TooManyOperands => write!(f, "too many operands for this instruction"),
+            ErrorType::ImmediateNotAllowed => write!(f, "this operand is written to or branched to, it cannot be immediate"),
+            ErrorType::ImmediateOutOfRange(value) => write!(f, "immediate value {value} does not fit, it must be below 1000"),
+            ErrorType::RegisterWhereMemoryExpected(name) => {
+                write!(f, "`{name}` is a register, a memory operand is expected here")
+            }
+            ErrorType::UndefinedSymbol(name) => write!(f, "undefined symbol `{name}`"),
+            ErrorType::InvalidNumber => write!(f, "expected a decimal number"),
+            ErrorType::NegativeValue => write!(f, "value must not be negative"),
+            ErrorType::WordOutOfRange(value) => {
+                write!(f, "{value} does not fit in a word, which holds {WORD_MIN} through {WORD_MAX}")
+            }
+            ErrorType::AddressOutOfRange(address) => {
+                write!(f, "address {address} is outside the 1000-word SMAC0 memory")
+            }
+            ErrorType::AddressInUse(address) => write!(f, "address {address} already holds a word"),
+            ErrorType::DuplicateLabel(name) => write!(f, "label `{name}` is defined more than once"),
+            ErrorType::UnusedSymbol(name) => write!(f, "label `{name}` is defined but never used"),
+            ErrorType::InvalidLiteral => write!(f, "literals are written as ='<number>'"),
+            ErrorType::InvalidExpression => write!(f, "expected a symbol or number, optionally with + and - offsets"),
+            ErrorType::ForwardReference(name) => write!(f, "symbol `{name}` has to be defined before it is used here"),
+            ErrorType::MissingSymbolName => write!(f, "EQU and SET need a symbol name"),
+            ErrorType::ExternalDefinedLocally(name) => {
+                write!(f, "`{name}` is declared EXTRN and cannot also be defined in this module")
+            }
+            ErrorType::ExternalEntry(name) => {
+                write!(f, "`{name}` is declared EXTRN and cannot also be an ENTRY of this module")
+            }
+            ErrorType::InvalidComparison => write!(f, "expected EQ, NE, LT, LE, GT or GE"),
+            ErrorType::UnmatchedConditional(directive) => write!(f, "{directive} without a matching IF"),
+            ErrorType::UnterminatedIf => write!(f, "IF without a matching ENDIF"),
+            ErrorType::InvalidConstant => write!(f, "constants are written as <number>, '<number>,<number>,...' or C'<text>'"),
+        }
+    }
+}
+
+// a point in the source: 1-based line number and a 0-based byte range of columns within that line
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SourceLocation {
+    pub line_number: usize,
+    pub columns: Range<usize>,
+    pub source_line: String,
+}
+
+// secondary location attached to a diagnostic, e.g. the earlier definition of a duplicate label
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Note {
+    pub message: String,
+    pub location: SourceLocation,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub file: String,
+    pub severity: Severity,
+    pub error_type: ErrorType,
+    pub location: SourceLocation,
+    pub notes: Vec<Note>,
+}
+
+impl Diagnostic {
+    pub fn line_number(&self) -> usize {
+        self.location.line_number
+    }
+
+    pub fn columns(&self) -> Range<usize> {
+        self.location.columns.clone()
+    }
+}
+
+// renders as
+//
+//     test.asm:3:7: error: unknown mnemonic
+//         3 | LOOP: FOO AREG NUM
+//           |       ^^^
+//
+// the first line follows the usual `file:line:col: severity: message` shape so editors can jump to it,
+// notes follow in the same shape
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_header(f, &self.file, &self.location, &self.severity, &self.error_type)?;
+        write_excerpt(f, &self.location)?;
+        for note in &self.notes {
+            writeln!(f)?;
+            write_header(f, &self.file, &note.location, &"note", &note.message)?;
+            write_excerpt(f, &note.location)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_header(
+    f: &mut fmt::Formatter<'_>,
+    file: &str,
+    location: &SourceLocation,
+    label: &dyn fmt::Display,
+    message: &dyn fmt::Display,
+) -> fmt::Result {
+    writeln!(
+        f,
+        "{}:{}:{}: {}: {}",
+        file,
+        location.line_number,
+        location.columns.start + 1,
+        label,
+        message
+    )
+}
+
+fn write_excerpt(f: &mut fmt::Formatter<'_>, location: &SourceLocation) -> fmt::Result {
+    let gutter = location.line_number.to_string();
+    let padding = " ".repeat(gutter.len());
+    let width = location.columns.len().max(1);
+    let indent = caret_indent(location);
+    writeln!(f, "    {gutter} | {}", location.source_line)?;
+    write!(f, "    {padding} | {indent}{}", "^".repeat(width))
+}
+
+// blanks up to the start of the span, keeping tabs so the carets line up with the source as the terminal draws it
+pub(crate) fn caret_indent(location: &SourceLocation) -> String {
+    location
+        .source_line
+        .get(..location.columns.start)
+        .unwrap_or(&location.source_line)
+        .chars()
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect()
+}
+
+impl std::error::Error for Diagnostic {}
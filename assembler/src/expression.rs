@@ -0,0 +1,87 @@
+use std::ops::Range;
+
+// a piece of an address expression such as `NUM+1` or `LOOP-2`
+#[derive(Debug, PartialEq)]
+pub(crate) enum Term<'a> {
+    Symbol(&'a str),
+    // never negative, the sign is on the SignedTerm. bounded by isize so sums can be checked
+    Number(isize),
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct SignedTerm<'a> {
+    pub negative: bool,
+    pub term: Term<'a>,
+    pub columns: Range<usize>,
+}
+
+// splits `text` (found at column `start` of its line) into terms joined by `+` and `-`.
+// returns None when the text is not a well formed expression
+pub(crate) fn parse_expression(text: &str, start: usize) -> Option<Vec<SignedTerm<'_>>> {
+    let mut terms = Vec::new();
+    let mut rest = text;
+    let mut offset = 0;
+    let mut negative = false;
+
+    loop {
+        let len = rest.find(['+', '-']).unwrap_or(rest.len());
+        let piece = &rest[..len];
+        let term = if piece.chars().all(|c| c.is_ascii_digit()) && !piece.is_empty() {
+            Term::Number(piece.parse::<isize>().ok()?)
+        } else if is_symbol_name(piece) {
+            Term::Symbol(piece)
+        } else {
+            return None;
+        };
+        terms.push(SignedTerm {
+            negative,
+            term,
+            columns: start + offset..start + offset + len,
+        });
+
+        match rest[len..].chars().next() {
+            Some(sign) => {
+                negative = sign == '-';
+                rest = &rest[len + 1..];
+                offset += len + 1;
+            }
+            None => return Some(terms),
+        }
+    }
+}
+
+pub(crate) fn is_symbol_name(text: &str) -> bool {
+    let mut chars = text.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_symbol_with_offsets() {
+        let terms = parse_expression("NUM+1-2", 10).unwrap();
+        assert_eq!(
+            terms,
+            vec![
+                SignedTerm { negative: false, term: Term::Symbol("NUM"), columns: 10..13 },
+                SignedTerm { negative: false, term: Term::Number(1), columns: 14..15 },
+                SignedTerm { negative: true, term: Term::Number(2), columns: 16..17 },
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_expressions() {
+        assert!(parse_expression("", 0).is_none());
+        assert!(parse_expression("NUM+", 0).is_none());
+        assert!(parse_expression("+1", 0).is_none());
+        assert!(parse_expression("1X", 0).is_none());
+        assert!(parse_expression("A*2", 0).is_none());
+        // a number past isize::MAX
+        assert!(parse_expression("X+9223372036854775808", 0).is_none());
+        assert!(parse_expression("9223372036854775807", 0).is_some());
+    }
+}
@@ -0,0 +1,279 @@
+// the machine the assembler targets: mnemonics with their opcode, operand shape and size, plus
+// register and condition code names. the SMAC0 set is built in, variants can be loaded from a
+// file in the format of `data/smac0.isa`
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::DIRECTIVES;
+
+const SMAC0: &str = include_str!("../data/smac0.isa");
+
+// what follows the mnemonic
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperandShape {
+    None,
+    // PUSH AREG, the mem field is left 0
+    Register,
+    RegisterMemory,
+    Memory,
+    ConditionMemory,
+    // DS: a word count, reserves space without emitting words
+    Storage,
+    // DC: the emitted word is the constant itself rather than OPCODE-REG-MEM
+    Constant,
+}
+
+impl OperandShape {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "none" => Some(OperandShape::None),
+            "reg" => Some(OperandShape::Register),
+            "reg-mem" => Some(OperandShape::RegisterMemory),
+            "mem" => Some(OperandShape::Memory),
+            "cond-mem" => Some(OperandShape::ConditionMemory),
+            "storage" => Some(OperandShape::Storage),
+            "constant" => Some(OperandShape::Constant),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction {
+    pub mnemonic: String,
+    pub opcode: usize,
+    pub shape: OperandShape,
+    // words the instruction takes up. the encoded word comes first, the rest are left empty
+    pub size: usize,
+    // the memory operand is written to, as by MOVEM and READ, so it cannot be an immediate value
+    pub stores: bool,
+    // the memory operand is where execution goes next, as for CALL. it cannot be immediate either,
+    // and the disassembler follows it. BC needs no flag, every cond-mem instruction branches
+    pub branches: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstructionSet {
+    // in the order they were described, names are upper case
+    instructions: Vec<Instruction>,
+    by_mnemonic: HashMap<String, usize>,
+    by_opcode: HashMap<usize, usize>,
+    registers: HashMap<String, usize>,
+    conditions: HashMap<String, usize>,
+    // code -> name for the disassembler, the first name described for a code wins
+    register_names: HashMap<usize, String>,
+    condition_names: HashMap<usize, String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct InstructionSetError {
+    pub line_number: usize,
+    pub message: String,
+}
+
+impl fmt::Display for InstructionSetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "instruction set line {}: {}", self.line_number, self.message)
+    }
+}
+
+impl std::error::Error for InstructionSetError {}
+
+impl Default for InstructionSet {
+    fn default() -> Self {
+        Self::smac0()
+    }
+}
+
+impl InstructionSet {
+    pub fn smac0() -> Self {
+        SMAC0.parse().expect("built-in SMAC0 instruction set is valid")
+    }
+
+    // read errors and format errors both come back as text, for the command line tools
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        text.parse().map_err(|e: InstructionSetError| e.to_string())
+    }
+
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+
+    // mnemonics, registers and condition codes are matched regardless of case
+    pub fn instruction(&self, mnemonic: &str) -> Option<&Instruction> {
+        let index = self.by_mnemonic.get(mnemonic.to_ascii_uppercase().as_str())?;
+        Some(&self.instructions[*index])
+    }
+
+    pub fn instruction_with_opcode(&self, opcode: usize) -> Option<&Instruction> {
+        self.by_opcode.get(&opcode).map(|&index| &self.instructions[index])
+    }
+
+    pub fn register(&self, name: &str) -> Option<usize> {
+        self.registers.get(name.to_ascii_uppercase().as_str()).copied()
+    }
+
+    pub fn condition(&self, name: &str) -> Option<usize> {
+        self.conditions.get(name.to_ascii_uppercase().as_str()).copied()
+    }
+
+    pub fn register_name(&self, code: usize) -> Option<&str> {
+        self.register_names.get(&code).map(String::as_str)
+    }
+
+    pub fn condition_name(&self, code: usize) -> Option<&str> {
+        self.condition_names.get(&code).map(String::as_str)
+    }
+
+    // the instruction literal pools are emitted with
+    pub(crate) fn constant_opcode(&self) -> usize {
+        self.instructions
+            .iter()
+            .find(|instruction| instruction.shape == OperandShape::Constant)
+            .map(|instruction| instruction.opcode)
+            .unwrap_or_default()
+    }
+}
+
+impl FromStr for InstructionSet {
+    type Err = InstructionSetError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut set = InstructionSet {
+            instructions: Vec::new(),
+            by_mnemonic: HashMap::new(),
+            by_opcode: HashMap::new(),
+            registers: HashMap::new(),
+            conditions: HashMap::new(),
+            register_names: HashMap::new(),
+            condition_names: HashMap::new(),
+        };
+
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let error = |message: String| InstructionSetError { line_number, message };
+            let code = line.split(';').next().unwrap_or_default();
+            let fields: Vec<&str> = code.split_whitespace().collect();
+            // OPCODE-REG-MEM packs the opcode into two digits and the register into one
+            let number = |field: usize, limit: usize| -> Result<usize, InstructionSetError> {
+                match fields.get(field).and_then(|text| text.parse::<usize>().ok()) {
+                    Some(value) if value < limit => Ok(value),
+                    Some(value) => Err(error(format!("{value} does not fit, it must be below {limit}"))),
+                    None => Err(error(String::from("expected a number"))),
+                }
+            };
+            let name = |field: usize| -> Result<String, InstructionSetError> {
+                fields
+                    .get(field)
+                    .map(|text| text.to_ascii_uppercase())
+                    .ok_or_else(|| error(String::from("expected a name")))
+            };
+
+            match fields.first().map(|kind| kind.to_ascii_uppercase()).as_deref() {
+                None => continue,
+                Some("INSTRUCTION") => {
+                    let mnemonic = name(1)?;
+                    let opcode = number(2, 100)?;
+                    let shape = fields
+                        .get(3)
+                        .and_then(|text| OperandShape::from_name(text))
+                        .ok_or_else(|| error(String::from("expected none, reg, reg-mem, mem, cond-mem, storage or constant")))?;
+                    let size = number(4, 1000)?;
+                    if size == 0 {
+                        return Err(error(String::from("an instruction takes up at least one word")));
+                    }
+                    let (stores, branches) = match fields.get(5).map(|text| text.to_ascii_lowercase()).as_deref() {
+                        None => (false, false),
+                        Some("stores") => (true, false),
+                        Some("branches") => (false, true),
+                        Some(_) => return Err(error(String::from("expected `stores`, `branches` or nothing after the size"))),
+                    };
+                    if DIRECTIVES.contains(&mnemonic.as_str()) {
+                        return Err(error(format!("`{mnemonic}` is an assembler directive")));
+                    }
+                    if set.by_mnemonic.contains_key(&mnemonic) {
+                        return Err(error(format!("`{mnemonic}` is described more than once")));
+                    }
+                    if set.by_opcode.contains_key(&opcode) {
+                        return Err(error(format!("opcode {opcode} is used more than once")));
+                    }
+                    set.by_mnemonic.insert(mnemonic.clone(), set.instructions.len());
+                    set.by_opcode.insert(opcode, set.instructions.len());
+                    set.instructions.push(Instruction {
+                        mnemonic,
+                        opcode,
+                        shape,
+                        size,
+                        stores,
+                        branches,
+                    });
+                }
+                Some("REGISTER") => {
+                    let (name, code) = (name(1)?, number(2, 10)?);
+                    if set.registers.insert(name.clone(), code).is_some() {
+                        return Err(error(String::from("register is described more than once")));
+                    }
+                    set.register_names.entry(code).or_insert(name);
+                }
+                Some("CONDITION") => {
+                    let (name, code) = (name(1)?, number(2, 10)?);
+                    if set.conditions.insert(name.clone(), code).is_some() {
+                        return Err(error(String::from("condition code is described more than once")));
+                    }
+                    set.condition_names.entry(code).or_insert(name);
+                }
+                Some(_) => return Err(error(String::from("expected INSTRUCTION, REGISTER or CONDITION"))),
+            }
+        }
+
+        // DC and literal pools both need a way to emit a plain word
+        if !set.instructions.iter().any(|instruction| instruction.shape == OperandShape::Constant) {
+            return Err(InstructionSetError {
+                line_number: 0,
+                message: String::from("no instruction with constant operands to store DC values and literals"),
+            });
+        }
+        Ok(set)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn built_in_set_is_smac0() {
+        let set = InstructionSet::smac0();
+        assert_eq!(set.instructions().len(), 17);
+        assert_eq!(set.instruction("bc").map(|i| (i.opcode, i.shape)), Some((7, OperandShape::ConditionMemory)));
+        assert_eq!(set.instruction_with_opcode(12).map(|i| i.mnemonic.as_str()), Some("DC"));
+        assert_eq!(set.register("dreg"), Some(3));
+        assert_eq!(set.condition("ANY"), Some(5));
+        assert_eq!(set.register_name(1), Some("BREG"));
+        assert_eq!(set.condition_name(4), Some("GE"));
+        assert_eq!(set.condition_name(6), None);
+        assert_eq!(set.constant_opcode(), 12);
+        assert!(set.instruction("MOVEM").is_some_and(|i| i.stores));
+        assert!(!set.instruction("MOVER").is_some_and(|i| i.stores));
+        assert!(set.instruction("CALL").is_some_and(|i| i.branches && i.shape == OperandShape::Memory));
+        assert_eq!(set.instruction("pop").map(|i| (i.opcode, i.shape)), Some((16, OperandShape::Register)));
+    }
+
+    #[test]
+    fn rejects_malformed_descriptions() {
+        let error = "INSTRUCTION DC 12 constant 1\nINSTRUCTION ADD 1 both 1\n"
+            .parse::<InstructionSet>()
+            .unwrap_err();
+        assert_eq!(error.line_number, 2);
+        assert!("INSTRUCTION DC 12 constant 1\nREGISTER EREG 10\n".parse::<InstructionSet>().is_err());
+        assert!("INSTRUCTION DC 12 constant 1\nINSTRUCTION DB 12 constant 1\n".parse::<InstructionSet>().is_err());
+        assert!("INSTRUCTION DC 12 constant 1\nINSTRUCTION END 1 none 1\n".parse::<InstructionSet>().is_err());
+        assert!("INSTRUCTION STOP 0 none 1\n".parse::<InstructionSet>().is_err());
+        assert!("INSTRUCTION DC 12 constant 1\nINSTRUCTION PUT 5 mem 1 loads\n".parse::<InstructionSet>().is_err());
+    }
+}
@@ -0,0 +1,72 @@
+// `--emit json`: the assembler's tables and diagnostics as one JSON document, for editor plugins and
+// grading scripts. field names follow the Rust field names and stay put, new fields may be added
+//
+//     {
+//       "file": "sum.asm",
+//       "entry": 100,
+//       "symbols": [{"name": "A", "address": 107, "defined": true, "used": true, ...}],
+//       "literals": [{"value": 5, "address": 110}],
+//       "intermediate_code": [{"line_number": 2, "address": 100, "opcode": 9, "reg": null,
+//                              "value": {"kind": "symbol", "value": 0}}],
+//       "machine_code": [{"address": 100, "word": 90107, "relocatable": true}],
+//       "diagnostics": [{"severity": "error", "code": "undefined_symbol", "argument": "B",
+//                        "message": "...", "file": "sum.asm", "location": {...}, "notes": []}]
+//     }
+//
+// errors come before warnings in "diagnostics"
+
+use serde::Serialize;
+
+use crate::{AssemblyOutput, Diagnostic, ErrorType, IntermediateCode, Literal, MachineCode, Note, Severity, SourceLocation, Symbol};
+
+#[derive(Serialize)]
+struct Output<'a> {
+    file: &'a str,
+    entry: usize,
+    symbols: &'a [Symbol],
+    literals: &'a [Literal],
+    intermediate_code: &'a [IntermediateCode],
+    machine_code: &'a [MachineCode],
+    diagnostics: Vec<JsonDiagnostic<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonDiagnostic<'a> {
+    severity: Severity,
+    #[serde(flatten)]
+    error_type: &'a ErrorType,
+    // the text `Display` prints after `error:`
+    message: String,
+    file: &'a str,
+    location: &'a SourceLocation,
+    notes: &'a [Note],
+}
+
+impl<'a> From<&'a Diagnostic> for JsonDiagnostic<'a> {
+    fn from(diagnostic: &'a Diagnostic) -> Self {
+        JsonDiagnostic {
+            severity: diagnostic.severity,
+            error_type: &diagnostic.error_type,
+            message: diagnostic.error_type.to_string(),
+            file: &diagnostic.file,
+            location: &diagnostic.location,
+            notes: &diagnostic.notes,
+        }
+    }
+}
+
+impl AssemblyOutput {
+    pub fn to_json(&self) -> String {
+        let output = Output {
+            file: &self.file_name,
+            entry: self.start_address,
+            symbols: &self.symbol_table,
+            literals: &self.literal_table,
+            intermediate_code: &self.intermediate_code_table,
+            machine_code: &self.machine_code_table,
+            diagnostics: self.error_table.iter().chain(&self.warning_table).map(JsonDiagnostic::from).collect(),
+        };
+        // only plain structs, strings and numbers, none of which can fail to serialize
+        serde_json::to_string_pretty(&output).expect("assembler tables serialize to JSON")
+    }
+}
@@ -1,382 +1,1351 @@
-// assembly instructions for previously built smac0:
-
-//     00 STOP:
-//         stop or halt execution
-//     01 ADD
-//     02 SUB
-//     03 MUL
-//     04 MOVER:
-//         move memory operand contents to register operand
-//     05 MOVEM:
-//         move register operand contents to memory
-//     06 COMP:
-//         compare register and memory operands to set condition code appropriately
-//     07 BC:
-//         branch to second operand depending on condition code as specified by first operand 
-//     08 DIV
-//     09 READ:
-//         read into memory operand
-//     10 PRINT:
-//         print contents of memory operand
-    // 11 DS:
-    //     reserve n memory locations under some name
-    
-    // 12 DC:
-    //     store some value in a memory location under some name
-
-const OPCODETABLE: [OpcodeStr; 13] = [
-    OpcodeStr { name: "STOP", code: 0 },
-    OpcodeStr { name: "ADD", code: 1 },
-    OpcodeStr { name: "SUB", code: 2 },
-    OpcodeStr { name: "MUL", code: 3 },
-    OpcodeStr { name: "MOVER", code: 4 },
-    OpcodeStr { name: "MOVEM", code: 5 },
-    OpcodeStr { name: "COMP", code: 6 },
-    OpcodeStr { name: "BC", code: 7 },
-    OpcodeStr { name: "DIV", code: 8 },
-    OpcodeStr { name: "READ", code: 9 },
-    OpcodeStr { name: "PRINT", code: 10 },
-    OpcodeStr { name: "DS", code: 11 },
-    OpcodeStr { name: "DC", code: 12 },
-]; 
-
-const REGISTERTABLE: [RegisterStr; 4] = [
-    RegisterStr { name: "AREG", code: 0 },
-    RegisterStr { name: "BREG", code: 1 },
-    RegisterStr { name: "CREG", code: 2 },
-    RegisterStr { name: "DREG", code: 3 },
-];
+// two-pass assembler for smac0. every instruction, with its opcode and operands, is listed in
+// data/smac0.isa, which instruction_set.rs builds in
 
-const CONDITIONTABLE: [ConditionCodeStr; 6] = [
-    ConditionCodeStr { name: "LT", code: 0 },
-    ConditionCodeStr { name: "LE", code: 1 },
-    ConditionCodeStr { name: "EQ", code: 2 },
-    ConditionCodeStr { name: "GT", code: 3 },
-    ConditionCodeStr { name: "GE", code: 4},
-    ConditionCodeStr { name: "ANY", code: 5},
-];
+mod conditional;
+mod diagnostic;
+mod expression;
+mod instruction_set;
+mod json;
+mod listing;
+mod object;
+mod tokenizer;
 
-pub struct Symbol {
-    pub name: String,
-    pub address: usize,
-    pub defined: bool,
-    pub used: bool,
-}
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::ops::Range;
+use std::slice::Iter;
 
-struct OpcodeStr {
-    name: &'static str,
-    code: usize,
-}
-impl From<&OpcodeStr> for Opcode {
-    fn from(op_str: &OpcodeStr) -> Self {
-        Opcode {
-            name: op_str.name.to_string(),
-            code: op_str.code,
-        }
-    }
-}
+use serde::Serialize;
+
+// SMAC0 has 1000 words of memory, addresses 000 through 999
+const MEMORY_SIZE: usize = 1000;
 
-struct RegisterStr {
-    name: &'static str,
-    code: usize,
+// a word is six decimal digits. negative values are kept in ten's complement, so -1 is stored as
+// 999999 and every word from 500000 up reads as negative
+pub const WORD_SIZE: usize = 1_000_000;
+pub const WORD_MIN: isize = -(WORD_SIZE as isize / 2);
+pub const WORD_MAX: isize = WORD_SIZE as isize / 2 - 1;
+
+// the stored form of a signed value, None when it does not fit in a word
+pub fn encode_word(value: isize) -> Option<usize> {
+    (WORD_MIN..=WORD_MAX)
+        .contains(&value)
+        .then(|| value.rem_euclid(WORD_SIZE as isize) as usize)
 }
-impl From<&RegisterStr> for Register {
-    fn from(reg_str: &RegisterStr) -> Self {
-        Register {
-            name: reg_str.name.to_string(),
-            code: reg_str.code,
-        }
+
+pub fn decode_word(word: usize) -> isize {
+    let word = (word % WORD_SIZE) as isize;
+    if word > WORD_MAX {
+        word - WORD_SIZE as isize
+    } else {
+        word
     }
 }
 
-struct ConditionCodeStr {
-    name: &'static str,
-    code: usize,
+// how an instruction gets at its operand. the mode is a seventh digit in front of OPCODE-REG-MEM:
+// 0 for direct, so those words keep their six digits, 1 for immediate and 2 plus the register code
+// for indexed
+#[derive(Debug, PartialEq, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case", tag = "kind", content = "register")]
+pub enum AddressingMode {
+    // `NUM`, the word at that address
+    Direct,
+    // `#5`, the mem field itself
+    Immediate,
+    // `NUM(BREG)`, the word at the address plus the value of the register
+    Indexed(usize),
 }
-impl From<&ConditionCodeStr> for ConditionCode {
-    fn from(cond_str: &ConditionCodeStr) -> Self {
-        ConditionCode {
-            name: cond_str.name.to_string(),
-            code: cond_str.code,
+
+const MODE_PLACE: usize = 1_000_000;
+// registers 0 through 7 fit in the mode digit as index registers
+const INDEX_REGISTERS: usize = 8;
+// the largest word an image or object module holds, an indexed instruction using every digit
+pub const MAX_WORD: usize = 10 * MODE_PLACE - 1;
+
+impl AddressingMode {
+    fn digit(self) -> usize {
+        match self {
+            AddressingMode::Direct => 0,
+            AddressingMode::Immediate => 1,
+            AddressingMode::Indexed(register) => 2 + register,
         }
     }
 }
 
-#[derive(Clone)]
-struct Opcode {
-    name: String,
-    code: usize,
+pub fn encode_instruction(mode: AddressingMode, opcode: usize, reg: usize, mem: usize) -> usize {
+    mode.digit() * MODE_PLACE + opcode * 10000 + reg * 1000 + mem
 }
 
-struct Register {
-    name: String,
-    code: usize,
+// (mode, opcode, reg, mem) of an instruction word
+pub fn decode_instruction(word: usize) -> (AddressingMode, usize, usize, usize) {
+    let mode = match word / MODE_PLACE {
+        0 => AddressingMode::Direct,
+        1 => AddressingMode::Immediate,
+        digit => AddressingMode::Indexed(digit - 2),
+    };
+    (mode, word / 10000 % 100, word / 1000 % 10, word % 1000)
 }
 
-struct ConditionCode {
-    name: String,
-    code: usize,
+// handled by the assembler itself rather than looked up in the instruction set
+const DIRECTIVES: [&str; 11] = [
+    "START", "END", "LTORG", "ORIGIN", "EQU", "ENTRY", "EXTRN", "IF", "ELSE", "ENDIF", "SET",
+];
+
+use conditional::Conditional;
+use expression::{parse_expression, Term};
+use tokenizer::{strip_comment, tokenize, Token};
+
+pub use diagnostic::{Diagnostic, ErrorType, Note, Severity, SourceLocation};
+pub use instruction_set::{Instruction, InstructionSet, InstructionSetError, OperandShape};
+pub use object::{ExternalReference, ObjectError, ObjectModule, PublicDefinition};
+
+#[derive(Serialize)]
+pub struct Symbol {
+    pub name: String,
+    pub address: usize,
+    pub defined: bool,
+    pub used: bool,
+    pub first_use: Option<SourceLocation>,
+    pub definition: Option<SourceLocation>,
+    // every line the symbol is referenced on, for the cross-reference
+    pub references: Vec<usize>,
+    // labels move with the program, EQU constants like `SIZE EQU 3` do not
+    pub relocatable: bool,
+    // named by ENTRY, so other modules can refer to it
+    pub public: bool,
+    // named by EXTRN, defined in some other module and resolved by the linker
+    pub external: bool,
+    // a SET variable, whose value can change from line to line
+    pub variable: bool,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case", tag = "kind", content = "value")]
 pub enum ValueKind {
-    Symbol,
-    Constant,
+    // index into the symbol table, resolved to an address in pass 2
+    Symbol(usize),
+    // a DC or literal word is already encoded, see `encode_word`
+    Constant(usize),
+    // index into the literal table, resolved once the literal's pool has been placed
+    Literal(usize),
 }
 
+#[derive(Serialize)]
 pub struct IntermediateCode {
+    pub line_number: usize,
     pub address: usize,
     pub opcode: usize,
     pub reg: Option<usize>,
-    pub kind: ValueKind,
-    pub value: usize,
+    pub value: ValueKind,
+    pub mode: AddressingMode,
+}
+
+#[derive(Serialize)]
+pub struct Literal {
+    pub value: isize,
+    pub address: Option<usize>,
 }
 
-#[derive(Debug)]
-enum ErrorType {
-    InvalidValue,
-    UnknownMnemonic,
-    InvalidOperand,
-    MissingLabel,
-    MissingConditionCode,
-    UndefinedSymbol(String)
+// a symbolic operand waiting for its address to be filled in by pass 2
+pub struct Backpatch {
+    pub ic_index: usize,
+    pub symbol: usize,
+    // constant part of an operand like `NUM+1`
+    pub offset: isize,
+    // the operand in the source, for an address pass 2 finds out of range
+    pub location: SourceLocation,
 }
 
-pub struct Error {
+// an operand as pass 1 leaves it, a symbol's address is added to `offset` in pass 2
+struct Operand {
+    value: ValueKind,
+    offset: isize,
+    mode: AddressingMode,
+    columns: Range<usize>,
+}
+
+// what the listing needs to know about each source line
+struct ListingLine {
     line_number: usize,
-    error_type: ErrorType,
+    address: Option<usize>,
+    source: String,
 }
 
-pub struct Assembler {
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MachineCode {
+    pub address: usize,
+    pub word: usize,
+    // the mem field holds an address that moves with the program when it is relocated
+    pub relocatable: bool,
+}
+
+// the `ADDR OPCODE-REG-MEM` text image loaded by `SMAC0::parse_file`, ending with `-1 <entry>`
+pub fn write_image<W: Write>(writer: &mut W, words: &[MachineCode], entry: usize) -> io::Result<()> {
+    for word in words {
+        writeln!(writer, "{:03} {:06}", word.address, word.word)?;
+    }
+    write!(writer, "-1 {entry:03}")
+}
+
+// None when the sum no longer fits, `A+A` for a huge EQU is an error rather than a wrapped value
+fn add_term(sum: isize, value: isize, negative: bool) -> Option<isize> {
+    if negative {
+        sum.checked_sub(value)
+    } else {
+        sum.checked_add(value)
+    }
+}
+
+// an operand that is a plain number, as STOP and DC have
+fn constant(value: usize) -> Operand {
+    Operand {
+        value: ValueKind::Constant(value),
+        offset: 0,
+        mode: AddressingMode::Direct,
+        columns: 0..0,
+    }
+}
+
+// the text between a pair of single quotes
+fn unquote(text: &str) -> Option<&str> {
+    text.strip_prefix('\'').and_then(|rest| rest.strip_suffix('\''))
+}
+
+// everything one unit produced. the listing, object module, image and JSON are written from here
+pub struct AssemblyOutput {
+    pub file_name: String,
+    pub start_address: usize,
+    // one past the highest address used, including DS space
+    pub program_end: usize,
     pub symbol_table: Vec<Symbol>,
-    opcode_table: Vec<Opcode>,
-    register_table: Vec<Register>,
-    condition_code_table: Vec<ConditionCode>,
+    pub literal_table: Vec<Literal>,
     pub intermediate_code_table: Vec<IntermediateCode>,
-    pub error_table: Vec<Error>,
+    pub backpatch_list: Vec<Backpatch>,
+    pub machine_code_table: Vec<MachineCode>,
+    pub error_table: Vec<Diagnostic>,
+    pub warning_table: Vec<Diagnostic>,
+    line_table: Vec<ListingLine>,
+}
+
+// an assembly session. settings such as the instruction set, -D defines and warnings carry over
+// from one unit to the next, everything else starts afresh with each unit
+pub struct Assembler {
+    // kept in definition order for the listing, `symbol_index` finds entries by name
+    symbol_table: Vec<Symbol>,
+    symbol_index: HashMap<String, usize>,
+    literal_table: Vec<Literal>,
+    // first literal that has not been placed by LTORG or END yet
+    pool_start: usize,
+    // value -> literal table index for the pool being collected
+    pool_index: HashMap<isize, usize>,
+    instruction_set: InstructionSet,
+    intermediate_code_table: Vec<IntermediateCode>,
+    backpatch_list: Vec<Backpatch>,
+    machine_code_table: Vec<MachineCode>,
+    line_table: Vec<ListingLine>,
+    error_table: Vec<Diagnostic>,
+    warning_table: Vec<Diagnostic>,
+    warnings_enabled: bool,
+    // source lines are only kept for a listing, a piped unit is otherwise never held in full
+    listing_enabled: bool,
+    // -D defines, given again to every unit
+    defines: Vec<(String, usize)>,
+    // later units see the symbols of earlier ones
+    shared_namespace: bool,
+    // name, address and relocatable of every label and EQU symbol the units so far have defined
+    shared_symbols: Vec<(String, usize, bool)>,
+    // open IF blocks, innermost last
+    conditionals: Vec<Conditional>,
     location_counter: usize,
+    start_address: usize,
+    // one past the highest address used, including DS space
+    program_end: usize,
+    // addresses that already hold a word
+    generated: Vec<bool>,
+    file_name: String,
+    line_number: usize,
+    source_line: String,
+}
+
+impl Default for Assembler {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Assembler {
     pub fn new() -> Self {
         Self {
             symbol_table: Vec::new(),
-            opcode_table: OPCODETABLE.iter().map(|op_str| Opcode::from(op_str)).collect(),
-            register_table: REGISTERTABLE.iter().map(|op_str| Register::from(op_str)).collect(),
-            condition_code_table: CONDITIONTABLE.iter().map(|op_str| ConditionCode::from(op_str)).collect(),
+            symbol_index: HashMap::new(),
+            literal_table: Vec::new(),
+            pool_start: 0,
+            pool_index: HashMap::new(),
+            instruction_set: InstructionSet::smac0(),
             intermediate_code_table: Vec::new(),
+            backpatch_list: Vec::new(),
+            machine_code_table: Vec::new(),
+            line_table: Vec::new(),
             error_table: Vec::new(),
+            warning_table: Vec::new(),
+            warnings_enabled: false,
+            listing_enabled: false,
+            defines: Vec::new(),
+            shared_namespace: false,
+            shared_symbols: Vec::new(),
+            conditionals: Vec::new(),
             location_counter: 0,
+            start_address: 0,
+            program_end: 0,
+            generated: vec![false; MEMORY_SIZE],
+            file_name: String::from("<source>"),
+            line_number: 0,
+            source_line: String::new(),
         }
     }
 
-    pub fn pass1(&mut self, source_lines: &[String]) {
-        for (line_number, line) in source_lines.iter().enumerate() {
-            let mut tokens = line.split_whitespace();
-            let mut label = None;
-            let mut mnemonic = "";
+    // file name reported in diagnostics
+    pub fn with_file_name(file_name: &str) -> Self {
+        Self {
+            file_name: file_name.to_string(),
+            ..Self::new()
+        }
+    }
 
-            if let Some(token) = tokens.next() {
-                if token.ends_with(":") {
-                    // If the token ends with a colon, it is a label
-                    label = Some(&token[..token.len() - 1]);
-                    mnemonic = tokens.next().unwrap_or("");
-                } else {
-                    mnemonic = token;
-                }
-            }
+    // for the next unit, when one session assembles several files
+    pub fn set_file_name(&mut self, file_name: &str) {
+        self.file_name = file_name.to_string();
+    }
+
+    // assemble for a SMAC0 variant instead of the built-in instruction set
+    pub fn use_instruction_set(&mut self, instruction_set: InstructionSet) {
+        self.instruction_set = instruction_set;
+    }
 
-            if let Some(label) = label {
-                self.add_symbol_as_label(label.to_string());
+    // an absolute symbol known before pass 1 starts, as given by `-D NAME=VALUE`
+    pub fn define(&mut self, name: &str, value: usize) {
+        self.defines.push((name.to_string(), value));
+        self.predefine(name, value, false);
+    }
+
+    fn predefine(&mut self, name: &str, value: usize, relocatable: bool) {
+        match self.find_symbol(name) {
+            Some(index) => self.symbol_table[index].address = value,
+            None => {
+                self.push_symbol(Symbol {
+                    name: name.to_string(),
+                    address: value,
+                    defined: true,
+                    used: false,
+                    first_use: None,
+                    definition: None,
+                    references: Vec::new(),
+                    relocatable,
+                    public: false,
+                    external: false,
+                    variable: false,
+                });
             }
+        }
+    }
 
-            if self.handle_start_and_end(mnemonic, &mut tokens) {
-                continue;
+    // warnings such as defined-but-unused labels are only collected once enabled
+    pub fn enable_warnings(&mut self) {
+        self.warnings_enabled = true;
+    }
+
+    // keeps every source line for `AssemblyOutput::listing`
+    pub fn enable_listing(&mut self) {
+        self.listing_enabled = true;
+    }
+
+    // units assembled from now on can refer to the labels and EQU symbols of the units before them,
+    // and defining one of those names again is a duplicate. labels are left to the linker, through
+    // D records in the unit that defines them and X records in the units that use them
+    pub fn share_namespace(&mut self) {
+        self.shared_namespace = true;
+    }
+
+    // assembles one unit. lines are numbered as given, blank and comment-only lines included, so
+    // diagnostics point at the right line of the file
+    pub fn assemble<I>(&mut self, source_lines: I) -> AssemblyOutput
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        self.reset();
+        for line in source_lines {
+            self.pass1_line(line.as_ref());
+        }
+        self.finish()
+    }
+
+    // straight from a file or a pipe, one line at a time
+    pub fn assemble_reader<R: BufRead>(&mut self, reader: R) -> io::Result<AssemblyOutput> {
+        self.reset();
+        for line in reader.lines() {
+            match line {
+                Ok(line) => self.pass1_line(&line),
+                Err(e) => {
+                    self.reset();
+                    return Err(e);
+                }
             }
+        }
+        Ok(self.finish())
+    }
 
-            if let Some(opcode_entry) = self.opcode_table.iter().find(|op| op.name == mnemonic) {
-                self.process_opcode(opcode_entry.code, &mut tokens, line_number);
+    // drops the unit being assembled, the session settings stay
+    pub fn reset(&mut self) {
+        *self = self.next_unit();
+    }
+
+    fn next_unit(&self) -> Assembler {
+        let mut unit = Assembler {
+            instruction_set: self.instruction_set.clone(),
+            warnings_enabled: self.warnings_enabled,
+            listing_enabled: self.listing_enabled,
+            defines: self.defines.clone(),
+            shared_namespace: self.shared_namespace,
+            shared_symbols: self.shared_symbols.clone(),
+            file_name: self.file_name.clone(),
+            ..Self::new()
+        };
+        for (name, address, relocatable) in &self.shared_symbols {
+            // a label moves with its unit when linked, so the linker resolves it like an EXTRN symbol
+            if *relocatable {
+                unit.declare_external(&Token { text: name, columns: 0..0 });
             } else {
-                self.error_table.push(Error {
-                    line_number,
-                    error_type: ErrorType::UnknownMnemonic,
-                });
+                unit.predefine(name, *address, false);
+            }
+        }
+        for (name, value) in &self.defines {
+            unit.predefine(name, *value, false);
+        }
+        unit
+    }
+
+    // feeds the next source line of the unit to pass 1, `finish` ends the unit
+    pub fn pass1_line(&mut self, line: &str) {
+        self.line_number += 1;
+        self.source_line.clear();
+        self.source_line.push_str(line);
+
+        let address = self.process_line(line);
+        self.program_end = self.program_end.max(self.location_counter);
+        if self.listing_enabled {
+            self.line_table.push(ListingLine {
+                line_number: self.line_number,
+                address,
+                source: line.to_string(),
+            });
+        }
+    }
+
+    // runs what is left of pass 1 and pass 2 over the lines fed so far, and leaves the session ready
+    // for the next unit
+    pub fn finish(&mut self) -> AssemblyOutput {
+        self.finish_pass1();
+        self.pass2();
+        if self.shared_namespace {
+            // -D defines and symbols from earlier units have no definition line
+            let defined = self
+                .symbol_table
+                .iter()
+                .filter(|symbol| symbol.defined && symbol.definition.is_some() && !symbol.variable)
+                .map(|symbol| (symbol.name.clone(), symbol.address, symbol.relocatable));
+            self.shared_symbols.extend(defined.collect::<Vec<_>>());
+            // later units refer to the labels through X records, which need a D record to resolve
+            for symbol in &mut self.symbol_table {
+                if symbol.defined && symbol.definition.is_some() && symbol.relocatable {
+                    symbol.public = true;
+                }
             }
         }
 
+        let next = self.next_unit();
+        let unit = std::mem::replace(self, next);
+
+        AssemblyOutput {
+            file_name: unit.file_name,
+            start_address: unit.start_address,
+            program_end: unit.program_end,
+            symbol_table: unit.symbol_table,
+            literal_table: unit.literal_table,
+            intermediate_code_table: unit.intermediate_code_table,
+            backpatch_list: unit.backpatch_list,
+            machine_code_table: unit.machine_code_table,
+            error_table: unit.error_table,
+            warning_table: unit.warning_table,
+            line_table: unit.line_table,
+        }
+    }
+
+    // the checks that need the whole program: literals still waiting for a pool, unterminated IFs,
+    // undefined and unused symbols
+    fn finish_pass1(&mut self) {
+        // a program without END still gets its literals
+        self.flush_literal_pool();
+        self.program_end = self.program_end.max(self.location_counter);
+        self.check_unterminated_conditionals();
         self.check_undefined_symbols();
+        self.check_unused_symbols();
     }
 
-    fn handle_start_and_end(&mut self, mnemonic: &str, tokens: &mut std::str::SplitWhitespace) -> bool {
+    // returns the address the line occupies, if it defines a label or takes up memory
+    fn process_line(&mut self, line: &str) -> Option<usize> {
+        let line_start = self.location_counter;
+        let line_tokens = tokenize(line);
+        let mut tokens = line_tokens.iter();
+        // blank and comment-only lines have nothing to assemble
+        let token = tokens.next()?;
+
+        let (label, mnemonic) = if let Some(stripped) = token.text.strip_suffix(':') {
+            // If the token ends with a colon, it is a label
+            let label = Token {
+                text: stripped,
+                columns: token.columns.start..token.columns.end - 1,
+            };
+            (Some(label), tokens.next())
+        } else if !self.is_mnemonic(token.text) && line_tokens.get(1).is_some_and(|next| self.is_mnemonic(next.text)) {
+            // a label without the colon, as in `A DS 1` coming out of the macro processor
+            (Some(token.clone()), tokens.next())
+        } else {
+            (None, Some(token))
+        };
+
+        let mnemonic_name = mnemonic.map(|m| m.text.to_ascii_uppercase());
+
+        match mnemonic_name.as_deref() {
+            Some("IF" | "ELSE" | "ENDIF") => {
+                let outside_assembled = self.process_conditional(mnemonic.unwrap_or(token), &mut tokens);
+                return match &label {
+                    Some(label) if outside_assembled => {
+                        self.add_symbol_as_label(label);
+                        Some(line_start)
+                    }
+                    _ => None,
+                };
+            }
+            // lines in a block whose condition does not hold are left out entirely
+            _ if !self.assembling() => return None,
+            // EQU and SET give their label the value of the expression instead of the location counter
+            Some("EQU") => return self.process_equ(label.as_ref(), &mut tokens),
+            Some("SET") => return self.process_set(label.as_ref(), &mut tokens),
+            // START moves the location counter first, so its label names the start address
+            Some("START") => {
+                self.handle_assembler_directive("START", &mut tokens);
+                self.add_symbol_as_label(label.as_ref()?);
+                return Some(self.location_counter);
+            }
+            _ => {}
+        }
+
+        if let Some(label) = &label {
+            self.add_symbol_as_label(label);
+        }
+
+        let Some(mnemonic) = mnemonic else {
+            self.report(self.end_of_line(), ErrorType::UnknownMnemonic);
+            return label.map(|_| line_start);
+        };
+
+        let mnemonic_name = mnemonic_name.unwrap_or_default();
+        if self.handle_assembler_directive(&mnemonic_name, &mut tokens) {
+            return None;
+        }
+
+        if let Some(instruction) = self.instruction_set.instruction(&mnemonic_name).cloned() {
+            self.process_instruction(&instruction, &mut tokens);
+        } else {
+            self.report(mnemonic.columns.clone(), ErrorType::UnknownMnemonic);
+        }
+
+        if label.is_some() || self.location_counter != line_start {
+            Some(line_start)
+        } else {
+            None
+        }
+    }
+
+    fn is_mnemonic(&self, text: &str) -> bool {
+        DIRECTIVES.iter().any(|name| name.eq_ignore_ascii_case(text))
+            || self.instruction_set.instruction(text).is_some()
+    }
+
+    fn handle_assembler_directive(&mut self, mnemonic: &str, tokens: &mut Iter<Token>) -> bool {
         match mnemonic {
             "START" => {
                 if let Some(addr_str) = tokens.next() {
-                    self.location_counter = addr_str.parse().expect("Invalid START address");
+                    if let Some(address) = self.parse_number(addr_str) {
+                        if address < MEMORY_SIZE {
+                            self.location_counter = address;
+                            self.start_address = address;
+                        } else {
+                            self.report(addr_str.columns.clone(), ErrorType::AddressOutOfRange(address));
+                        }
+                    }
                 }
+                self.check_extra_operands(tokens);
                 true
             }
-            "END" => {
+            "ORIGIN" => {
+                if let Some(expr) = tokens.next() {
+                    match self.absolute_expression(expr).map(|(address, _)| address) {
+                        Some(address) if address < 0 => {
+                            self.report(expr.columns.clone(), ErrorType::NegativeValue);
+                        }
+                        Some(address) if address as usize >= MEMORY_SIZE => {
+                            self.report(expr.columns.clone(), ErrorType::AddressOutOfRange(address as usize));
+                        }
+                        Some(address) => self.location_counter = address as usize,
+                        None => {}
+                    }
+                } else {
+                    self.report(self.end_of_line(), ErrorType::InvalidOperand);
+                }
+                self.check_extra_operands(tokens);
+                true
+            }
+            "ENTRY" => {
+                for token in tokens {
+                    // counts as a use, so a missing definition is reported like any other
+                    let index = self.add_symbol(token);
+                    if self.symbol_table[index].external {
+                        self.report(token.columns.clone(), ErrorType::ExternalEntry(token.text.to_string()));
+                        continue;
+                    }
+                    self.symbol_table[index].public = true;
+                }
+                true
+            }
+            "EXTRN" => {
+                for token in tokens {
+                    self.declare_external(token);
+                }
+                true
+            }
+            // both place the pending literal pool at the current location
+            "LTORG" | "END" => {
+                self.check_extra_operands(tokens);
+                self.flush_literal_pool();
                 true
             }
             _ => false
         }
     }
 
-    fn process_opcode(&mut self, opcode_code: usize, tokens: &mut std::str::SplitWhitespace, line_number: usize) {
-        match opcode_code {
-            0 => self.generate_intermediate_code(0, None, ValueKind::Constant, 0),
-            1 | 2 | 3 | 8 | 4 | 5 | 6 => {
-                let (reg_code, kind, value) = self.process_operands(tokens);
-                self.generate_intermediate_code(opcode_code, reg_code, kind, value);
+    fn flush_literal_pool(&mut self) {
+        for index in self.pool_start..self.literal_table.len() {
+            self.literal_table[index].address = Some(self.location_counter);
+            // checked against the word range when the literal was added
+            let word = encode_word(self.literal_table[index].value).unwrap_or_default();
+            let opcode = self.instruction_set.constant_opcode();
+            self.generate_intermediate_code(opcode, None, constant(word));
+        }
+        self.pool_start = self.literal_table.len();
+        self.pool_index.clear();
+    }
+
+    fn process_equ(&mut self, label: Option<&Token>, tokens: &mut Iter<Token>) -> Option<usize> {
+        let Some(label) = label else {
+            self.report(self.whole_line(), ErrorType::MissingSymbolName);
+            return None;
+        };
+        let Some(expr) = tokens.next() else {
+            self.report(self.end_of_line(), ErrorType::InvalidOperand);
+            return None;
+        };
+        self.check_extra_operands(tokens);
+        match self.absolute_expression(expr)? {
+            (value, _) if value < 0 => {
+                self.report(expr.columns.clone(), ErrorType::NegativeValue);
+                None
+            }
+            (value, relocatable) => {
+                self.define_symbol(label, value as usize, relocatable);
+                Some(value as usize)
+            }
+        }
+    }
+
+    // `NUM`, `#5` or `NUM(BREG)`. an immediate operand is whatever would go in the mem field, so
+    // `#SIZE` is the value of an EQU symbol and `#ARR` the address of ARR
+    fn addressed_operand(&mut self, token: &Token, immediate_allowed: bool) -> Option<Operand> {
+        let (inner, mode) = if let Some(rest) = token.text.strip_prefix('#') {
+            if !immediate_allowed {
+                self.report(token.columns.clone(), ErrorType::ImmediateNotAllowed);
+                return None;
+            }
+            let columns = token.columns.start + 1..token.columns.end;
+            (Token { text: rest, columns }, AddressingMode::Immediate)
+        } else if let Some((address, register)) = token.text.strip_suffix(')').and_then(|text| text.rsplit_once('(')) {
+            let start = token.columns.start;
+            let register_columns = start + address.len() + 1..start + address.len() + 1 + register.len();
+            let index = self.instruction_set.register(register).filter(|&code| code < INDEX_REGISTERS);
+            let Some(index) = index else {
+                self.report(register_columns, ErrorType::InvalidValue);
+                return None;
+            };
+            let columns = start..start + address.len();
+            (Token { text: address, columns }, AddressingMode::Indexed(index))
+        } else {
+            (token.clone(), AddressingMode::Direct)
+        };
+        // a literal is somewhere in memory, `#='5'` would be the address of that
+        if inner.text.is_empty() || (mode == AddressingMode::Immediate && inner.text.starts_with('=')) {
+            self.report(token.columns.clone(), ErrorType::InvalidOperand);
+            return None;
+        }
+
+        let (value, offset) = self.memory_operand(&inner)?;
+        if let ValueKind::Constant(value) = value {
+            if value >= MEMORY_SIZE {
+                let error_type = match mode {
+                    AddressingMode::Immediate => ErrorType::ImmediateOutOfRange(value),
+                    _ => ErrorType::AddressOutOfRange(value),
+                };
+                self.report(inner.columns.clone(), error_type);
+                return None;
+            }
+        }
+        Some(Operand {
+            value,
+            offset,
+            mode,
+            columns: inner.columns,
+        })
+    }
+
+    // a memory operand: a literal, a plain number, or a symbol with constant offsets like `NUM+1`.
+    // the symbol may be defined later, its address is filled in by pass 2
+    fn memory_operand(&mut self, token: &Token) -> Option<(ValueKind, isize)> {
+        // `MOVER AREG BREG`, SMAC0 has no register to register instructions
+        if self.instruction_set.register(token.text).is_some() {
+            let name = token.text.to_string();
+            self.report(token.columns.clone(), ErrorType::RegisterWhereMemoryExpected(name));
+            return None;
+        }
+        if token.text.starts_with('=') {
+            return self.add_literal(token).map(|literal| (ValueKind::Literal(literal), 0));
+        }
+        let Some(terms) = parse_expression(token.text, token.columns.start) else {
+            self.report(token.columns.clone(), ErrorType::InvalidExpression);
+            return None;
+        };
+
+        let mut symbol = None;
+        let mut offset: isize = 0;
+        for term in terms {
+            let value = match term.term {
+                Term::Number(n) => n,
+                Term::Symbol(name) => {
+                    // SET variables are constants as of this line
+                    if let Some(index) = self.find_symbol(name).filter(|&index| self.symbol_table[index].variable) {
+                        let variable = &mut self.symbol_table[index];
+                        variable.used = true;
+                        variable.references.push(self.line_number);
+                        variable.address as isize
+                    } else {
+                        // an address is one symbol plus or minus constants
+                        if symbol.is_some() || term.negative {
+                            self.report(token.columns.clone(), ErrorType::InvalidExpression);
+                            return None;
+                        }
+                        symbol = Some(self.add_symbol(&Token { text: name, columns: term.columns }));
+                        continue;
+                    }
+                }
+            };
+            let Some(sum) = add_term(offset, value, term.negative) else {
+                self.report(token.columns.clone(), ErrorType::InvalidExpression);
+                return None;
+            };
+            offset = sum;
+        }
+
+        match symbol {
+            Some(symbol) => Some((ValueKind::Symbol(symbol), offset)),
+            None if offset < 0 => {
+                self.report(token.columns.clone(), ErrorType::NegativeValue);
+                None
+            }
+            None => Some((ValueKind::Constant(offset as usize), 0)),
+        }
+    }
+
+    // an expression that has to be known right away (EQU, ORIGIN), so every symbol in it
+    // must already be defined. also says whether the result is an address that moves with the program:
+    // `ARR+2` does, `END-ARR` and `3` do not
+    fn absolute_expression(&mut self, token: &Token) -> Option<(isize, bool)> {
+        let Some(terms) = parse_expression(token.text, token.columns.start) else {
+            self.report(token.columns.clone(), ErrorType::InvalidExpression);
+            return None;
+        };
+
+        let mut value: isize = 0;
+        let mut relocatable_terms = 0;
+        for term in terms {
+            let term_value = match term.term {
+                Term::Number(n) => n,
+                Term::Symbol(name) => {
+                    let index = self.find_symbol(name).filter(|&index| self.symbol_table[index].defined);
+                    let Some(index) = index else {
+                        self.report(term.columns, ErrorType::ForwardReference(name.to_string()));
+                        return None;
+                    };
+                    let symbol = &mut self.symbol_table[index];
+                    symbol.used = true;
+                    symbol.references.push(self.line_number);
+                    if symbol.relocatable {
+                        relocatable_terms += if term.negative { -1 } else { 1 };
+                    }
+                    symbol.address as isize
+                }
+            };
+            let Some(sum) = add_term(value, term_value, term.negative) else {
+                self.report(token.columns.clone(), ErrorType::InvalidExpression);
+                return None;
+            };
+            value = sum;
+        }
+
+        // sums like `A+B` of two addresses mean nothing once the program moves
+        match relocatable_terms {
+            0 => Some((value, false)),
+            1 => Some((value, true)),
+            _ => {
+                self.report(token.columns.clone(), ErrorType::InvalidExpression);
+                None
+            }
+        }
+    }
+
+    // `='5'` refers to a word holding 5, placed in the next literal pool. `='-5'` works too
+    fn add_literal(&mut self, token: &Token) -> Option<usize> {
+        let digits = token.text.strip_prefix("='").and_then(|rest| rest.strip_suffix('\''));
+        let Some(value) = digits.and_then(|digits| digits.parse::<isize>().ok()) else {
+            self.report(token.columns.clone(), ErrorType::InvalidLiteral);
+            return None;
+        };
+        if encode_word(value).is_none() {
+            self.report(token.columns.clone(), ErrorType::WordOutOfRange(value));
+            return None;
+        }
+
+        // the same literal is only stored once per pool
+        if let Some(&index) = self.pool_index.get(&value) {
+            return Some(index);
+        }
+        self.literal_table.push(Literal { value, address: None });
+        self.pool_index.insert(value, self.literal_table.len() - 1);
+        Some(self.literal_table.len() - 1)
+    }
+
+    fn process_instruction(&mut self, instruction: &Instruction, tokens: &mut Iter<Token>) {
+        let (opcode, shape, size) = (instruction.opcode, instruction.shape, instruction.size);
+        // an address to write to or jump to has to be a real address
+        let immediate_allowed = !instruction.stores && !instruction.branches;
+        let start = self.location_counter;
+        match shape {
+            OperandShape::None => self.generate_intermediate_code(opcode, None, constant(0)),
+            OperandShape::Register => match tokens.next() {
+                Some(register) => match self.instruction_set.register(register.text) {
+                    Some(reg_code) => self.generate_intermediate_code(opcode, Some(reg_code), constant(0)),
+                    None => self.report(register.columns.clone(), ErrorType::InvalidValue),
+                },
+                None => self.report(self.end_of_line(), ErrorType::MissingOperand),
+            },
+            OperandShape::RegisterMemory => {
+                let (reg_code, operand) = self.process_operands(immediate_allowed, tokens);
+                self.generate_intermediate_code(opcode, reg_code, operand);
             }
-            9 | 10 => {
+            OperandShape::Memory => {
                 if let Some(operand) = tokens.next() {
-                    let value = self.add_symbol(operand.to_string());
-                    self.generate_intermediate_code(opcode_code, None, ValueKind::Symbol, value);
+                    if let Some(operand) = self.addressed_operand(operand, immediate_allowed) {
+                        self.generate_intermediate_code(opcode, None, operand);
+                    }
+                } else {
+                    self.report(self.end_of_line(), ErrorType::MissingOperand);
                 }
             }
-            7 => {
+            OperandShape::ConditionMemory => {
                 if let Some(cond_code) = tokens.next() {
-                    let reg_code = self.condition_code_table.iter().find(|c| c.name == cond_code).map(|c| c.code);
-                    
+                    let reg_code = self.instruction_set.condition(cond_code.text);
+                    if reg_code.is_none() {
+                        let name = cond_code.text.to_string();
+                        self.report(cond_code.columns.clone(), ErrorType::UnknownConditionCode(name));
+                    }
+
                     if let Some(label) = tokens.next() {
-                        let value = self.add_symbol(label.to_string());
-                        self.generate_intermediate_code(7, reg_code, ValueKind::Symbol, value);
+                        // a branch target can be indexed, as in a jump table, but not immediate
+                        if let Some(operand) = self.addressed_operand(label, false) {
+                            self.generate_intermediate_code(opcode, reg_code, operand);
+                        }
                     } else {
-                        self.error_table.push(Error {
-                            line_number,
-                            error_type: ErrorType::MissingLabel,
-                        });
+                        self.report(self.end_of_line(), ErrorType::MissingLabel);
                     }
                 } else {
-                    self.error_table.push(Error {
-                        line_number,
-                        error_type: ErrorType::MissingConditionCode,
-                    });
+                    self.report(self.end_of_line(), ErrorType::MissingConditionCode);
                 }
             }
-            11 => self.process_ds(tokens),
-            12 => self.process_dc(tokens, line_number),
-            _ => unreachable!()
+            OperandShape::Storage => self.process_ds(tokens),
+            OperandShape::Constant => self.process_dc(opcode, tokens),
+        }
+        self.check_extra_operands(tokens);
+
+        // words after the first one of a longer instruction are left empty
+        if size > 1 && self.location_counter > start {
+            self.reserve(size - 1, self.whole_line());
         }
     }
 
-    fn process_ds(&mut self, tokens: &mut std::str::SplitWhitespace) {
+    // anything left on the line once the instruction has taken its operands
+    fn check_extra_operands(&mut self, tokens: &mut Iter<Token>) {
+        if let Some(first) = tokens.next() {
+            let end = tokens.last().map_or(first.columns.end, |last| last.columns.end);
+            self.report(first.columns.start..end, ErrorType::TooManyOperands);
+        }
+    }
+
+    fn process_ds(&mut self, tokens: &mut Iter<Token>) {
         if let Some(size_str) = tokens.next() {
-            let size: usize = size_str.parse().expect("Invalid DS size");
-            self.location_counter += size;
+            if let Some(size) = self.storage_size(size_str) {
+                self.reserve(size, size_str.columns.clone());
+            }
+        } else {
+            self.report(self.end_of_line(), ErrorType::InvalidOperand);
         }
     }
 
-    fn process_dc(&mut self, tokens: &mut std::str::SplitWhitespace, line_number: usize) {
-        if let Some(value_str) = tokens.next() {
-            let value: usize = value_str.parse().expect("Invalid DC value");
-            self.intermediate_code_table.push(IntermediateCode {
-                address: self.location_counter,
-                opcode: 12,
-                reg: None,
-                kind: ValueKind::Constant,
-                value,
-            });
-            self.location_counter += 1;
+    // moves the location counter past `words` words without emitting anything
+    fn reserve(&mut self, words: usize, columns: Range<usize>) {
+        // the last reserved word has to fit in memory as well
+        if self.location_counter + words <= MEMORY_SIZE {
+            self.location_counter += words;
         } else {
-            self.error_table.push(Error {
-                line_number,
-                error_type: ErrorType::InvalidOperand,
-            });
+            let last = self.location_counter + words - 1;
+            self.report(columns, ErrorType::AddressOutOfRange(last));
+        }
+    }
+
+    // a plain number, or an expression over symbols defined earlier as in `DS SIZE+1`
+    fn storage_size(&mut self, token: &Token) -> Option<usize> {
+        if token.text.parse::<i64>().is_ok() {
+            return self.parse_number(token);
+        }
+        match self.absolute_expression(token)? {
+            (size, _) if size < 0 => {
+                self.report(token.columns.clone(), ErrorType::NegativeValue);
+                None
+            }
+            // an address is not a size, it would change once the program is relocated
+            (_, true) => {
+                self.report(token.columns.clone(), ErrorType::InvalidExpression);
+                None
+            }
+            (size, false) => Some(size as usize),
         }
     }
 
-    fn add_symbol(&mut self, name: String) -> usize {
-        if let Some(symbol) = self.symbol_table.iter_mut().find(|sym| sym.name == name) {
-            symbol.used = true;
-            symbol.address
+    fn process_dc(&mut self, opcode: usize, tokens: &mut Iter<Token>) {
+        let Some(value_str) = tokens.next() else {
+            self.report(self.end_of_line(), ErrorType::InvalidOperand);
+            return;
+        };
+        for value in self.constant_values(value_str).unwrap_or_default() {
+            let address = self.location_counter;
+            self.generate_intermediate_code(opcode, None, constant(value));
+            // out of memory, already reported once
+            if self.location_counter == address {
+                break;
+            }
+        }
+    }
+
+    // `5` or `-5`, `'5,-10,15'` for a word per number, or `C'HELLO'` for a word per character code.
+    // the values come back encoded as words
+    fn constant_values(&mut self, token: &Token) -> Option<Vec<usize>> {
+        let text = token.text;
+        if let Some(characters) = text.strip_prefix(['C', 'c']).and_then(unquote) {
+            if characters.is_empty() {
+                self.report(token.columns.clone(), ErrorType::InvalidConstant);
+                return None;
+            }
+            return characters
+                .chars()
+                .map(|c| self.encode(c as isize, token.columns.clone()))
+                .collect();
+        }
+        if text.starts_with('\'') {
+            let Some(list) = unquote(text).filter(|list| !list.is_empty()) else {
+                self.report(token.columns.clone(), ErrorType::InvalidConstant);
+                return None;
+            };
+            let mut values = Vec::new();
+            let mut start = token.columns.start + 1;
+            for item in list.split(',') {
+                let trimmed = item.trim_start();
+                let column = start + item.len() - trimmed.len();
+                let trimmed = trimmed.trim_end();
+                values.push(self.parse_word(&Token {
+                    text: trimmed,
+                    columns: column..column + trimmed.len(),
+                })?);
+                start += item.len() + 1;
+            }
+            return Some(values);
+        }
+        self.parse_word(token).map(|value| vec![value])
+    }
+
+    // a signed decimal for DC, encoded as a word
+    fn parse_word(&mut self, token: &Token) -> Option<usize> {
+        match token.text.parse::<isize>() {
+            Ok(value) => self.encode(value, token.columns.clone()),
+            Err(_) => {
+                self.report(token.columns.clone(), ErrorType::InvalidNumber);
+                None
+            }
+        }
+    }
+
+    fn encode(&mut self, value: isize, columns: Range<usize>) -> Option<usize> {
+        let word = encode_word(value);
+        if word.is_none() {
+            self.report(columns, ErrorType::WordOutOfRange(value));
+        }
+        word
+    }
+
+    fn add_symbol(&mut self, token: &Token) -> usize {
+        if let Some(index) = self.find_symbol(token.text) {
+            self.symbol_table[index].used = true;
+            self.symbol_table[index].references.push(self.line_number);
+            index
         } else {
-            self.symbol_table.push(Symbol {
-                name: name.clone(),
+            let first_use = self.location(token.columns.clone());
+            self.push_symbol(Symbol {
+                name: token.text.to_string(),
                 address: 0,
                 defined: false,
                 used: true,
-            });
-            self.symbol_table.len() - 1
+                first_use: Some(first_use),
+                definition: None,
+                references: vec![self.line_number],
+                relocatable: false,
+                public: false,
+                external: false,
+                variable: false,
+            })
+        }
+    }
+
+    fn find_symbol(&self, name: &str) -> Option<usize> {
+        self.symbol_index.get(name).copied()
+    }
+
+    fn push_symbol(&mut self, symbol: Symbol) -> usize {
+        let index = self.symbol_table.len();
+        self.symbol_index.insert(symbol.name.clone(), index);
+        self.symbol_table.push(symbol);
+        index
+    }
+
+    fn add_symbol_as_label(&mut self, token: &Token) -> usize {
+        self.define_symbol(token, self.location_counter, true)
+    }
+
+    fn declare_external(&mut self, token: &Token) {
+        match self.find_symbol(token.text) {
+            Some(index) if self.symbol_table[index].defined => {
+                self.report(token.columns.clone(), ErrorType::ExternalDefinedLocally(token.text.to_string()));
+            }
+            // the EXTRN wins, so the symbol is not also reported as undefined
+            Some(index) if self.symbol_table[index].public => {
+                self.report(token.columns.clone(), ErrorType::ExternalEntry(token.text.to_string()));
+                self.symbol_table[index].public = false;
+                self.symbol_table[index].external = true;
+            }
+            Some(index) => self.symbol_table[index].external = true,
+            None => {
+                self.push_symbol(Symbol {
+                    name: token.text.to_string(),
+                    address: 0,
+                    defined: false,
+                    used: false,
+                    first_use: None,
+                    definition: None,
+                    references: Vec::new(),
+                    relocatable: false,
+                    public: false,
+                    external: true,
+                    variable: false,
+                });
+            }
         }
     }
 
-    fn add_symbol_as_label(&mut self, name: String) -> usize {
-        if let Some(symbol) = self.symbol_table.iter_mut().find(|sym| sym.name == name) {
+    fn define_symbol(&mut self, token: &Token, address: usize, relocatable: bool) -> usize {
+        let definition = self.location(token.columns.clone());
+        if let Some(index) = self.find_symbol(token.text) {
+            if self.symbol_table[index].external {
+                // labels of earlier units in a shared namespace are external here, but were never declared so
+                let error_type = if self.shared_symbols.iter().any(|(name, ..)| name == token.text) {
+                    ErrorType::DuplicateLabel(token.text.to_string())
+                } else {
+                    ErrorType::ExternalDefinedLocally(token.text.to_string())
+                };
+                self.report(token.columns.clone(), error_type);
+                return 0;
+            }
+            if self.symbol_table[index].defined {
+                // keep the first definition, later ones are mistakes. `-D` defines have no line to point at
+                let notes = self.symbol_table[index].definition.clone().map(|previous| Note {
+                    message: String::from("first defined here"),
+                    location: previous,
+                });
+                self.push_diagnostic(Diagnostic {
+                    file: self.file_name.clone(),
+                    severity: Severity::Error,
+                    error_type: ErrorType::DuplicateLabel(token.text.to_string()),
+                    location: definition,
+                    notes: notes.into_iter().collect(),
+                });
+                return self.symbol_table[index].address;
+            }
+            let symbol = &mut self.symbol_table[index];
             symbol.defined = true;
-            symbol.address = self.location_counter;
-            symbol.address
+            symbol.address = address;
+            symbol.definition = Some(definition);
+            symbol.relocatable = relocatable;
+            address
         } else {
-            let address = self.location_counter;
-            self.symbol_table.push(Symbol {
-                name: name.clone(),
+            self.push_symbol(Symbol {
+                name: token.text.to_string(),
                 address,
                 defined: true,
                 used: false,
+                first_use: None,
+                definition: Some(definition),
+                references: Vec::new(),
+                relocatable,
+                public: false,
+                external: false,
+                variable: false,
             });
             address
         }
     }
 
     #[inline]
-    fn process_operands(&mut self, tokens: &mut dyn Iterator<Item = &str>) -> (Option<usize>, ValueKind, usize) {
+    fn process_operands(&mut self, immediate_allowed: bool, tokens: &mut Iter<Token>) -> (Option<usize>, Operand) {
         let mut reg_code = None;
-        let mut kind = ValueKind::Symbol;
-        let mut value = 0;
-    
-        if let Some(register_str) = tokens.next() {
-            if let Some(register) = self.register_table.iter().find(|r| r.name == register_str) {
-                reg_code = Some(register.code);
-            } else {
-                self.error_table.push(Error {
-                    line_number: self.location_counter, 
-                    error_type: ErrorType::InvalidValue,
-                });
-                return (reg_code, kind, value); 
-            }
+        let mut operand = constant(0);
+
+        let Some(register_str) = tokens.next() else {
+            self.report(self.end_of_line(), ErrorType::MissingOperand);
+            return (reg_code, operand);
+        };
+        // the memory operand is still taken off the line, so it doesn't count as one too many
+        let operand_str = tokens.next();
+        if let Some(code) = self.instruction_set.register(register_str.text) {
+            reg_code = Some(code);
+        } else {
+            self.report(register_str.columns.clone(), ErrorType::InvalidValue);
+            return (reg_code, operand);
         }
 
-        if let Some(operand_str) = tokens.next() {
-            if let Ok(constant_value) = operand_str.parse::<usize>() {
-                kind = ValueKind::Constant;
-                value = constant_value;
-            } else {
-                value = self.add_symbol(operand_str.to_string());
+        match operand_str {
+            Some(operand_str) => {
+                if let Some(addressed) = self.addressed_operand(operand_str, immediate_allowed) {
+                    operand = addressed;
+                }
             }
+            None => self.report(self.end_of_line(), ErrorType::MissingOperand),
         }
-    
-        (reg_code, kind, value)
+        (reg_code, operand)
     }
 
-    fn generate_intermediate_code(&mut self, opcode: usize, reg: Option<usize>, kind: ValueKind, value: usize) {
+    fn generate_intermediate_code(&mut self, opcode: usize, reg: Option<usize>, operand: Operand) {
+        if self.location_counter >= MEMORY_SIZE {
+            self.report(self.whole_line(), ErrorType::AddressOutOfRange(self.location_counter));
+            return;
+        }
+        // ORIGIN can move back over words already generated, the image would then hold both
+        if self.generated[self.location_counter] {
+            self.report(self.whole_line(), ErrorType::AddressInUse(self.location_counter));
+            self.location_counter += 1;
+            return;
+        }
+        self.generated[self.location_counter] = true;
+        if let ValueKind::Symbol(symbol) = operand.value {
+            self.backpatch_list.push(Backpatch {
+                ic_index: self.intermediate_code_table.len(),
+                symbol,
+                offset: operand.offset,
+                location: self.location(operand.columns),
+            });
+        }
         self.intermediate_code_table.push(IntermediateCode {
+            line_number: self.line_number,
             address: self.location_counter,
             opcode,
             reg,
-            kind,
-            value,
+            value: operand.value,
+            mode: operand.mode,
         });
         self.location_counter += 1;
     }
 
+    // numeric operands of directives are plain unsigned decimals
+    fn parse_number(&mut self, token: &Token) -> Option<usize> {
+        match token.text.parse::<i64>() {
+            Ok(value) if value < 0 => {
+                self.report(token.columns.clone(), ErrorType::NegativeValue);
+                None
+            }
+            Ok(value) => Some(value as usize),
+            Err(_) => {
+                self.report(token.columns.clone(), ErrorType::InvalidNumber);
+                None
+            }
+        }
+    }
+
+    fn location(&self, columns: Range<usize>) -> SourceLocation {
+        SourceLocation {
+            line_number: self.line_number,
+            columns,
+            source_line: self.source_line.clone(),
+        }
+    }
+
+    // used for operands that are missing altogether, points just past the last token before any comment
+    fn end_of_line(&self) -> Range<usize> {
+        let end = strip_comment(&self.source_line).trim_end().len();
+        end..end + 1
+    }
+
+    fn whole_line(&self) -> Range<usize> {
+        let code = strip_comment(&self.source_line);
+        let start = code.len() - code.trim_start().len();
+        start..code.trim_end().len()
+    }
+
+    fn report(&mut self, columns: Range<usize>, error_type: ErrorType) {
+        let location = self.location(columns);
+        self.report_at(Severity::Error, error_type, location);
+    }
+
+    fn report_at(&mut self, severity: Severity, error_type: ErrorType, location: SourceLocation) {
+        self.push_diagnostic(Diagnostic {
+            file: self.file_name.clone(),
+            severity,
+            error_type,
+            location,
+            notes: Vec::new(),
+        });
+    }
+
+    fn push_diagnostic(&mut self, diagnostic: Diagnostic) {
+        match diagnostic.severity {
+            Severity::Error => self.error_table.push(diagnostic),
+            Severity::Warning => {
+                if self.warnings_enabled {
+                    self.warning_table.push(diagnostic);
+                }
+            }
+        }
+    }
+
     fn check_undefined_symbols(&mut self) {
-        for symbol in &self.symbol_table {
-            if symbol.used && !symbol.defined {
-                self.error_table.push(Error {
-                    line_number: 0, 
-                    error_type: ErrorType::UndefinedSymbol(symbol.name.clone()),
-                });
+        let undefined: Vec<(String, SourceLocation)> = self
+            .symbol_table
+            .iter()
+            .filter(|symbol| symbol.used && !symbol.defined && !symbol.external)
+            .filter_map(|symbol| Some((symbol.name.clone(), symbol.first_use.clone()?)))
+            .collect();
+        for (name, location) in undefined {
+            self.report_at(Severity::Error, ErrorType::UndefinedSymbol(name), location);
+        }
+    }
+
+    fn check_unused_symbols(&mut self) {
+        let unused: Vec<(String, SourceLocation)> = self
+            .symbol_table
+            .iter()
+            .filter(|symbol| symbol.defined && !symbol.used)
+            .filter_map(|symbol| Some((symbol.name.clone(), symbol.definition.clone()?)))
+            .collect();
+        for (name, location) in unused {
+            self.report_at(Severity::Warning, ErrorType::UnusedSymbol(name), location);
+        }
+    }
+
+    fn pass2(&mut self) {
+        for entry in &self.intermediate_code_table {
+            // symbolic operands are left as 0 here and filled in from the backpatch list below
+            let operand = match entry.value {
+                ValueKind::Constant(value) => value,
+                ValueKind::Symbol(_) => 0,
+                ValueKind::Literal(index) => self.literal_table[index].address.unwrap_or(0),
+            };
+            // DC stores its constant as-is, everything else is packed as OPCODE-REG-MEM
+            let constant = self.instruction_set.instruction_with_opcode(entry.opcode);
+            let word = if constant.is_some_and(|instruction| instruction.shape == OperandShape::Constant) {
+                operand
+            } else {
+                encode_instruction(entry.mode, entry.opcode, entry.reg.unwrap_or(0), operand)
+            };
+            self.machine_code_table.push(MachineCode {
+                address: entry.address,
+                word,
+                relocatable: matches!(entry.value, ValueKind::Literal(_)),
+            });
+        }
+
+        let mut out_of_range = Vec::new();
+        for patch in &self.backpatch_list {
+            let symbol = &self.symbol_table[patch.symbol];
+            // external references are left to the linker, offset and all, through their X record
+            if symbol.external || !symbol.defined {
+                continue;
             }
+            let address = (symbol.address as isize).checked_add(patch.offset);
+            if let Some(address) = address.filter(|address| (0..MEMORY_SIZE as isize).contains(address)) {
+                let entry = &mut self.machine_code_table[patch.ic_index];
+                entry.word += address as usize;
+                entry.relocatable = symbol.relocatable;
+            } else {
+                out_of_range.push((address, patch.location.clone()));
+            }
+        }
+        for (address, location) in out_of_range {
+            // the mem field of a word has no way to hold an address below zero
+            let error_type = match address {
+                Some(address) if address < 0 => ErrorType::NegativeValue,
+                Some(address) => ErrorType::AddressOutOfRange(address as usize),
+                None => ErrorType::InvalidExpression,
+            };
+            self.report_at(Severity::Error, error_type, location);
         }
+        // the errors found here go in among the pass 1 ones, so the table reads in line order
+        self.error_table.sort_by_key(Diagnostic::line_number);
+    }
+}
+
+impl AssemblyOutput {
+    pub fn write_machine_code<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write_image(writer, &self.machine_code_table, self.start_address)
     }
 
     pub fn print_intermediate_code(&self) {
         println!("Intermediate Code Table:");
         for entry in &self.intermediate_code_table {
             println!(
-                "Address: {}, Opcode: {}, Reg: {:?}, Kind: {:?}, Value: {}",
-                entry.address, entry.opcode, entry.reg, entry.kind, entry.value
+                "Address: {}, Opcode: {}, Reg: {:?}, Value: {:?}",
+                entry.address, entry.opcode, entry.reg, entry.value
             );
         }
     }
@@ -391,13 +1360,22 @@ impl Assembler {
         }
     }
 
+    pub fn print_machine_code(&self) {
+        println!("Machine Code:");
+        for entry in &self.machine_code_table {
+            println!("Address: {}, Word: {:06}", entry.address, entry.word);
+        }
+    }
+
     pub fn print_error_table(&self) {
-        println!("Error Table:");
-        for symbol in &self.error_table {
-            println!(
-                "Line number: {}, Error type: {:?}",
-                symbol.line_number, symbol.error_type
-            );
+        for diagnostic in &self.error_table {
+            eprintln!("{diagnostic}");
+        }
+    }
+
+    pub fn print_warning_table(&self) {
+        for diagnostic in &self.warning_table {
+            eprintln!("{diagnostic}");
         }
     }
 }
@@ -0,0 +1,104 @@
+use std::fmt::Write;
+
+use crate::diagnostic::caret_indent;
+use crate::{AssemblyOutput, Diagnostic};
+
+// width of the `LINE  LOC  WORD     ` columns in front of the source text. the word column fits
+// seven digits, instructions that are not direct addressing have a mode digit in front
+const PREFIX_WIDTH: usize = 20;
+
+impl AssemblyOutput {
+    // side-by-side listing: line number, location counter, generated word and the source line, with
+    // diagnostics under the line they belong to, followed by a symbol cross-reference. the source
+    // lines are only there when the unit was assembled with `enable_listing`
+    pub fn listing(&self) -> String {
+        let mut out = String::new();
+        let mut ic_index = 0;
+
+        let _ = writeln!(out, "LINE  LOC  WORD     SOURCE");
+        for line in &self.line_table {
+            let mut words = Vec::new();
+            while ic_index < self.intermediate_code_table.len()
+                && self.intermediate_code_table[ic_index].line_number == line.line_number
+            {
+                let word = format!("{:06}", self.machine_code_table[ic_index].word);
+                words.push((self.intermediate_code_table[ic_index].address, word));
+                ic_index += 1;
+            }
+
+            let (first_address, first_word) = match words.first() {
+                Some((address, word)) => (format!("{address:03}"), word.clone()),
+                None => (line.address.map(|a| format!("{a:03}")).unwrap_or_default(), String::new()),
+            };
+            let row = format!("{:>4}  {:>3}  {:7}  {}", line.line_number, first_address, first_word, line.source);
+            let _ = writeln!(out, "{}", row.trim_end());
+            // lines that emit more than one word get a row per extra word
+            for (address, word) in words.iter().skip(1) {
+                let _ = writeln!(out, "{:>4}  {:03}  {}", "", address, word);
+            }
+
+            for diagnostic in self.diagnostics_on(line.line_number) {
+                write_inline_diagnostic(&mut out, diagnostic);
+            }
+        }
+
+        let _ = writeln!(out);
+        self.write_cross_reference(&mut out);
+        out
+    }
+
+    fn diagnostics_on(&self, line_number: usize) -> impl Iterator<Item = &Diagnostic> {
+        self.error_table
+            .iter()
+            .chain(&self.warning_table)
+            .filter(move |diagnostic| diagnostic.line_number() == line_number)
+    }
+
+    fn write_cross_reference(&self, out: &mut String) {
+        let mut symbols: Vec<_> = self.symbol_table.iter().collect();
+        symbols.sort_by(|a, b| a.name.cmp(&b.name));
+        let name_width = symbols.iter().map(|s| s.name.len()).max().unwrap_or(0).max("SYMBOL".len());
+
+        let _ = writeln!(out, "{:name_width$}  ADDR  DEFINED  REFERENCES", "SYMBOL");
+        for symbol in symbols {
+            let address = if symbol.defined { format!("{:03}", symbol.address) } else { String::from("***") };
+            // `-D` defines have no defining line
+            let defined = symbol
+                .definition
+                .as_ref()
+                .map_or_else(|| String::from("-"), |definition| definition.line_number.to_string());
+            let references: Vec<String> = symbol.references.iter().map(|line| line.to_string()).collect();
+            let row = format!(
+                "{:name_width$}  {:>4}  {:>7}  {}",
+                symbol.name,
+                address,
+                defined,
+                references.join(" ")
+            );
+            let _ = writeln!(out, "{}", row.trim_end());
+        }
+
+        if !self.literal_table.is_empty() {
+            let _ = writeln!(out);
+            let _ = writeln!(out, "LITERAL  ADDR");
+            for literal in &self.literal_table {
+                let address = literal.address.map(|a| format!("{a:03}")).unwrap_or_else(|| String::from("***"));
+                let _ = writeln!(out, "{:8} {:>4}", format!("='{}'", literal.value), address);
+            }
+        }
+    }
+}
+
+fn write_inline_diagnostic(out: &mut String, diagnostic: &Diagnostic) {
+    let columns = diagnostic.columns();
+    let indent = caret_indent(&diagnostic.location);
+    let _ = writeln!(
+        out,
+        "{:PREFIX_WIDTH$}{}{} {}: {}",
+        "",
+        indent,
+        "^".repeat(columns.len().max(1)),
+        diagnostic.severity,
+        diagnostic.error_type
+    );
+}
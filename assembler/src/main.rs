@@ -1,104 +1,251 @@
+use std::env;
 use std::fs::File;
-use std::io::{self, BufRead};
-use std::path::Path;
+use std::io::{self, BufRead, Write};
+use std::process::ExitCode;
 
-fn main() {
-    let file_path = r"C:\codes\systems_programming_exercises\assembler\data\test.asm";
-    let source_lines: Vec<String> = read_lines(file_path).expect("Failed to read file");
+use assembler::InstructionSet;
 
-    let mut assembler = assembler::Assembler::new();
-    assembler.pass1(&source_lines);
+const USAGE: &str = "usage: assembler <input.asm | -> [-o out.sm] [--object out.obj] [--listing out.lst] [--isa machine.isa] [-D NAME=VALUE]... [--symbols] [--ic] [--warnings] [--emit json]";
 
-    assembler.print_intermediate_code();
-    assembler.print_symbol_table();
-    assembler.print_error_table();
+#[derive(Debug, PartialEq)]
+struct Options {
+    input: String,
+    output: Option<String>,
+    object: Option<String>,
+    listing: Option<String>,
+    instruction_set: Option<String>,
+    defines: Vec<(String, usize)>,
+    symbols: bool,
+    intermediate_code: bool,
+    warnings: bool,
+    // tables and diagnostics as JSON on stdout instead of text
+    json: bool,
 }
 
-fn read_lines<P>(filename: P) -> io::Result<Vec<String>>
-where
-    P: AsRef<Path>,
-{
-    let file = File::open(filename)?;
-    let lines = io::BufReader::new(file).lines();
-    let result: Vec<String> = lines
-        .filter_map(|line| line.ok()) 
-        .map(|line| line.trim().to_string()) 
-        .filter(|line| !line.is_empty()) 
-        .collect();
-    Ok(result)
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+    let mut input = None;
+    let mut options = Options {
+        input: String::new(),
+        output: None,
+        object: None,
+        listing: None,
+        instruction_set: None,
+        defines: Vec::new(),
+        symbols: false,
+        intermediate_code: false,
+        warnings: false,
+        json: false,
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => {
+                let path = args.next().ok_or("-o needs an output file")?;
+                options.output = Some(path);
+            }
+            "--object" => {
+                let path = args.next().ok_or("--object needs an output file")?;
+                options.object = Some(path);
+            }
+            "--listing" => {
+                let path = args.next().ok_or("--listing needs an output file")?;
+                options.listing = Some(path);
+            }
+            "--isa" => {
+                let path = args.next().ok_or("--isa needs an instruction set file")?;
+                options.instruction_set = Some(path);
+            }
+            "-D" => {
+                let define = args.next().ok_or("-D needs NAME=VALUE")?;
+                options.defines.push(parse_define(&define)?);
+            }
+            // also accepted without the space, as in `-DDEBUG=1`
+            flag if flag.len() > 2 && flag.starts_with("-D") => options.defines.push(parse_define(&flag[2..])?),
+            "--symbols" => options.symbols = true,
+            "--ic" => options.intermediate_code = true,
+            "--warnings" => options.warnings = true,
+            "--emit" => match args.next().as_deref() {
+                Some("json") => options.json = true,
+                Some(format) => return Err(format!("unknown --emit format `{format}`, expected json")),
+                None => return Err(String::from("--emit needs a format")),
+            },
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            // a lone `-` means stdin, anything else starting with `-` is a typo
+            flag if flag.starts_with('-') && flag != "-" => return Err(format!("unknown option `{flag}`")),
+            _ => {
+                if input.replace(arg).is_some() {
+                    return Err(String::from("only one input file can be assembled at a time"));
+                }
+            }
+        }
+    }
+
+    options.input = input.ok_or("no input file given")?;
+    Ok(options)
+}
+
+// `NAME=VALUE`, or just `NAME` for 1
+fn parse_define(define: &str) -> Result<(String, usize), String> {
+    let (name, value) = define.split_once('=').unwrap_or((define, "1"));
+    let valid_name = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid_name {
+        return Err(format!("invalid symbol name in -D `{define}`"));
+    }
+    let value = value.parse().map_err(|_| format!("invalid value in -D `{define}`"))?;
+    Ok((name.to_string(), value))
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{message}");
+            eprintln!("{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+
+    let source = match open_source(&options.input) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("{}: {e}", options.input);
+            return ExitCode::from(2);
+        }
+    };
+
+    let file_name = if options.input == "-" { "<stdin>" } else { options.input.as_str() };
+    let mut assembler = assembler::Assembler::with_file_name(file_name);
+    if let Some(path) = &options.instruction_set {
+        match InstructionSet::from_file(path) {
+            Ok(instruction_set) => assembler.use_instruction_set(instruction_set),
+            Err(message) => {
+                eprintln!("{path}: {message}");
+                return ExitCode::from(2);
+            }
+        }
+    }
+    for (name, value) in &options.defines {
+        assembler.define(name, *value);
+    }
+    if options.warnings {
+        assembler.enable_warnings();
+    }
+    if options.listing.is_some() {
+        assembler.enable_listing();
+    }
+    // read as it is assembled, so without --listing piped macro processor output is never held in full
+    // pass 2 runs even with errors so the listing shows as much as possible
+    let output = match assembler.assemble_reader(source) {
+        Ok(output) => output,
+        Err(e) => {
+            eprintln!("{}: {e}", options.input);
+            return ExitCode::from(2);
+        }
+    };
+
+    if !options.json {
+        if options.intermediate_code {
+            output.print_intermediate_code();
+        }
+        if options.symbols {
+            output.print_symbol_table();
+        }
+        output.print_warning_table();
+    }
+
+    if let Some(path) = &options.listing {
+        if let Err(e) = std::fs::write(path, output.listing()) {
+            eprintln!("{path}: {e}");
+            return ExitCode::from(2);
+        }
+    }
+    // the diagnostics are part of the JSON, so it is printed whether or not there were errors
+    if options.json {
+        println!("{}", output.to_json());
+    }
+
+    if !output.error_table.is_empty() {
+        if !options.json {
+            output.print_error_table();
+        }
+        eprintln!("{} error(s), no output written", output.error_table.len());
+        return ExitCode::FAILURE;
+    }
+
+    let written = match &options.output {
+        Some(path) => File::create(path).and_then(|mut file| output.write_machine_code(&mut file)),
+        // stdout already has the JSON on it
+        None if options.json => Ok(()),
+        None => {
+            let mut stdout = io::stdout().lock();
+            output.write_machine_code(&mut stdout).and_then(|_| writeln!(stdout))
+        }
+    };
+    if let Err(e) = written {
+        eprintln!("{}: {e}", options.output.as_deref().unwrap_or("<stdout>"));
+        return ExitCode::from(2);
+    }
+
+    if let Some(path) = &options.object {
+        if let Err(e) = std::fs::write(path, output.object_module().to_string()) {
+            eprintln!("{path}: {e}");
+            return ExitCode::from(2);
+        }
+    }
+
+    ExitCode::SUCCESS
+}
+
+fn open_source(input: &str) -> io::Result<Box<dyn BufRead>> {
+    if input == "-" {
+        Ok(Box::new(io::stdin().lock()))
+    } else {
+        Ok(Box::new(io::BufReader::new(File::open(input)?)))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use assembler::{ValueKind, IntermediateCode};
+
+    fn args(line: &str) -> impl Iterator<Item = String> + '_ {
+        line.split_whitespace().map(String::from)
+    }
 
     #[test]
-    fn it_works() {
-        let source_lines: Vec<String> = r#"
-START 300
-BEGIN: READ NUM
-LOOP: MOVEM AREG NUM
-PRINT NUM
-MUL AREG NUM
-COMP AREG HUNDRED
-BC LT LOOP
-STOP
-NUM: DS 2
-HUNDRED: DC 100
-END
-"#
-        .lines()
-        .map(str::trim)
-        .filter(|line| !line.is_empty())
-        .map(String::from)
-        .collect();
-        let mut assembler = assembler::Assembler::new();
-        assembler.pass1(&source_lines);
-
-        assert!(assembler.error_table.is_empty());
-
-        assert_eq!(assembler.symbol_table.len(), 4);
-
-        assert_eq!(assembler.symbol_table[0].name, "BEGIN");
-        assert_eq!(assembler.symbol_table[0].address, 300);
-        assert!(assembler.symbol_table[0].defined);
-        assert!(!assembler.symbol_table[0].used);
-
-        assert_eq!(assembler.symbol_table[1].name, "NUM");
-        assert_eq!(assembler.symbol_table[1].address, 307);
-        assert!(assembler.symbol_table[1].defined);
-        assert!(assembler.symbol_table[1].used);
-
-        assert_eq!(assembler.symbol_table[2].name, "LOOP");
-        assert_eq!(assembler.symbol_table[2].address, 301);
-        assert!(assembler.symbol_table[2].defined);
-        assert!(assembler.symbol_table[2].used);
-
-        assert_eq!(assembler.symbol_table[3].name, "HUNDRED");
-        assert_eq!(assembler.symbol_table[3].address, 309);
-        assert!(assembler.symbol_table[3].defined);
-        assert!(assembler.symbol_table[3].used);
-
-        assert_eq!(assembler.intermediate_code_table.len(), 8);
-
-        let expected = vec![
-            IntermediateCode { address: 300, opcode: 9, reg: None, kind: ValueKind::Symbol, value: 1 },
-            IntermediateCode { address: 301, opcode: 5, reg: Some(0), kind: ValueKind::Symbol, value: 0 },
-            IntermediateCode { address: 302, opcode: 10, reg: None, kind: ValueKind::Symbol, value: 0 },
-            IntermediateCode { address: 303, opcode: 3, reg: Some(0), kind: ValueKind::Symbol, value: 0 },
-            IntermediateCode { address: 304, opcode: 6, reg: Some(0), kind: ValueKind::Symbol, value: 3 },
-            IntermediateCode { address: 305, opcode: 7, reg: Some(0), kind: ValueKind::Symbol, value: 301 },
-            IntermediateCode { address: 306, opcode: 0, reg: None, kind: ValueKind::Constant, value: 0 },
-            IntermediateCode { address: 309, opcode: 12, reg: None, kind: ValueKind::Constant, value: 100 },
-        ];
-
-        for (entry, expected_entry) in assembler.intermediate_code_table.iter().zip(expected.iter()) {
-            assert_eq!(entry.address, expected_entry.address);
-            assert_eq!(entry.opcode, expected_entry.opcode);
-            assert_eq!(entry.reg, expected_entry.reg);
-            assert_eq!(entry.kind, expected_entry.kind);
-            assert_eq!(entry.value, expected_entry.value);
-        }
+    fn parses_command_line() {
+        let options = parse_args(args("prog.asm -o prog.sm --object prog.obj --listing prog.lst --isa big.isa -D DEBUG=2 -DTRACE --symbols --ic")).unwrap();
+        assert_eq!(
+            options,
+            Options {
+                input: String::from("prog.asm"),
+                output: Some(String::from("prog.sm")),
+                object: Some(String::from("prog.obj")),
+                listing: Some(String::from("prog.lst")),
+                instruction_set: Some(String::from("big.isa")),
+                defines: vec![(String::from("DEBUG"), 2), (String::from("TRACE"), 1)],
+                symbols: true,
+                intermediate_code: true,
+                warnings: false,
+                json: false,
+            }
+        );
+
+        let stdin = parse_args(args("- --warnings --emit json")).unwrap();
+        assert_eq!(stdin.input, "-");
+        assert_eq!(stdin.output, None);
+        assert!(stdin.warnings);
+        assert!(stdin.json);
+
+        assert!(parse_args(args("")).is_err());
+        assert!(parse_args(args("a.asm b.asm")).is_err());
+        assert!(parse_args(args("a.asm -o")).is_err());
+        assert!(parse_args(args("a.asm --isa")).is_err());
+        assert!(parse_args(args("a.asm -D 1X=2")).is_err());
+        assert!(parse_args(args("a.asm -D DEBUG=yes")).is_err());
+        assert!(parse_args(args("a.asm --bogus")).is_err());
+        assert!(parse_args(args("a.asm --emit")).is_err());
+        assert!(parse_args(args("a.asm --emit xml")).is_err());
     }
 }
@@ -0,0 +1,271 @@
+// object module format, one record per line:
+//
+//     H <name> <origin> <length>    header: module name, address it was assembled at, words it spans
+//     T <address> <word>            text: one word of the program
+//     R <address>                   relocation: the mem field of the word at this address holds an address
+//     D <name> <address> R|A        definition: a symbol named by ENTRY, visible to other modules. R for
+//                                   an address in the module, A for an absolute value such as an EQU 5
+//     X <address> <name> <offset>   external reference: the address of `name` plus the offset, which may be
+//                                   negative, goes in the mem field of the word at this address
+//     E <address>                   entry point
+//
+// loading the module somewhere other than its origin adds the difference to every word named by an R record
+// and to every relocatable D address. X records are left for the linker, which knows where every module ends up
+//
+// words are written as six unsigned digits. a DC of a negative number is stored in ten's complement,
+// so `DC -1` gives `T <address> 999999`, see `encode_word`. an instruction that uses immediate or
+// indexed addressing has a seventh digit in front for the mode, see `AddressingMode`
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{AssemblyOutput, MachineCode, MAX_WORD, MEMORY_SIZE};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjectModule {
+    pub name: String,
+    pub origin: usize,
+    pub length: usize,
+    pub text: Vec<MachineCode>,
+    pub relocations: Vec<usize>,
+    pub definitions: Vec<PublicDefinition>,
+    pub external_references: Vec<ExternalReference>,
+    pub entry: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PublicDefinition {
+    pub name: String,
+    pub address: usize,
+    pub relocatable: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExternalReference {
+    pub address: usize,
+    pub name: String,
+    pub offset: isize,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ObjectError {
+    pub line_number: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ObjectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "object line {}: {}", self.line_number, self.message)
+    }
+}
+
+impl std::error::Error for ObjectError {}
+
+impl AssemblyOutput {
+    // the assembled program as a relocatable object module, to be called after pass 2
+    pub fn object_module(&self) -> ObjectModule {
+        ObjectModule {
+            name: self.module_name(),
+            origin: self.start_address,
+            length: self.program_end.saturating_sub(self.start_address),
+            text: self.machine_code_table.clone(),
+            relocations: self
+                .machine_code_table
+                .iter()
+                .filter(|entry| entry.relocatable)
+                .map(|entry| entry.address)
+                .collect(),
+            definitions: self
+                .symbol_table
+                .iter()
+                .filter(|symbol| symbol.public && symbol.defined)
+                .map(|symbol| PublicDefinition {
+                    name: symbol.name.clone(),
+                    address: symbol.address,
+                    relocatable: symbol.relocatable,
+                })
+                .collect(),
+            external_references: self
+                .backpatch_list
+                .iter()
+                .filter(|patch| self.symbol_table[patch.symbol].external)
+                .map(|patch| ExternalReference {
+                    address: self.intermediate_code_table[patch.ic_index].address,
+                    name: self.symbol_table[patch.symbol].name.clone(),
+                    offset: patch.offset,
+                })
+                .collect(),
+            entry: self.start_address,
+        }
+    }
+
+    // file name without directories or extension, e.g. `sum` for `data/sum.asm`
+    fn module_name(&self) -> String {
+        let base = self.file_name.rsplit(['/', '\\']).next().unwrap_or_default();
+        let stem = base.split('.').next().unwrap_or_default();
+        if stem.is_empty() || stem.starts_with('<') {
+            String::from("MAIN")
+        } else {
+            stem.to_ascii_uppercase()
+        }
+    }
+}
+
+impl ObjectModule {
+    // the module's words moved to `load_origin`, with every address-sensitive word adjusted
+    pub fn relocate(&self, load_origin: usize) -> Result<Vec<MachineCode>, String> {
+        let mut image = Vec::with_capacity(self.text.len());
+        for entry in &self.text {
+            let address = self
+                .shift(entry.address, load_origin)
+                .ok_or_else(|| format!("word {:03} ends up outside memory", entry.address))?;
+            let word = if self.relocations.contains(&entry.address) {
+                // only the 3-digit mem field holds the address
+                let mem = self
+                    .shift(entry.word % 1000, load_origin)
+                    .ok_or_else(|| format!("address in word {:03} ends up outside memory", entry.address))?;
+                entry.word - entry.word % 1000 + mem
+            } else {
+                entry.word
+            };
+            image.push(MachineCode {
+                address,
+                word,
+                relocatable: false,
+            });
+        }
+        Ok(image)
+    }
+
+    pub fn relocated_entry(&self, load_origin: usize) -> Option<usize> {
+        self.shift(self.entry, load_origin)
+    }
+
+    // absolute definitions keep their value wherever the module is loaded
+    pub fn relocated_definition(&self, definition: &PublicDefinition, load_origin: usize) -> Option<usize> {
+        if definition.relocatable {
+            self.shift(definition.address, load_origin)
+        } else {
+            Some(definition.address)
+        }
+    }
+
+    fn shift(&self, address: usize, load_origin: usize) -> Option<usize> {
+        let shifted = address as isize - self.origin as isize + load_origin as isize;
+        (0..MEMORY_SIZE as isize).contains(&shifted).then_some(shifted as usize)
+    }
+}
+
+impl fmt::Display for ObjectModule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "H {} {:03} {:03}", self.name, self.origin, self.length)?;
+        for entry in &self.text {
+            writeln!(f, "T {:03} {:06}", entry.address, entry.word)?;
+        }
+        for address in &self.relocations {
+            writeln!(f, "R {address:03}")?;
+        }
+        for definition in &self.definitions {
+            let kind = if definition.relocatable { "R" } else { "A" };
+            writeln!(f, "D {} {:03} {kind}", definition.name, definition.address)?;
+        }
+        for reference in &self.external_references {
+            writeln!(f, "X {:03} {} {}", reference.address, reference.name, reference.offset)?;
+        }
+        writeln!(f, "E {:03}", self.entry)
+    }
+}
+
+impl FromStr for ObjectModule {
+    type Err = ObjectError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut module: Option<ObjectModule> = None;
+
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let error = |message: &str| ObjectError {
+                line_number,
+                message: message.to_string(),
+            };
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let name = |field: usize| -> Result<String, ObjectError> {
+                fields
+                    .get(field)
+                    .map(|text| text.to_string())
+                    .ok_or_else(|| error("expected a symbol name"))
+            };
+            let number = |field: usize| -> Result<usize, ObjectError> {
+                fields
+                    .get(field)
+                    .and_then(|text| text.parse().ok())
+                    .ok_or_else(|| error("expected a number"))
+            };
+
+            match fields.first().copied() {
+                None => continue,
+                Some("H") => {
+                    if module.is_some() {
+                        return Err(error("more than one header record"));
+                    }
+                    let name = fields.get(1).ok_or_else(|| error("header without a module name"))?;
+                    module = Some(ObjectModule {
+                        name: name.to_string(),
+                        origin: number(2)?,
+                        length: number(3)?,
+                        text: Vec::new(),
+                        relocations: Vec::new(),
+                        definitions: Vec::new(),
+                        external_references: Vec::new(),
+                        entry: 0,
+                    });
+                }
+                Some(kind) => {
+                    let module = module.as_mut().ok_or_else(|| error("record before the header"))?;
+                    match kind {
+                        "T" => {
+                            let word = number(2)?;
+                            if word > MAX_WORD {
+                                return Err(error("word has more than seven digits"));
+                            }
+                            module.text.push(MachineCode {
+                                address: number(1)?,
+                                word,
+                                relocatable: false,
+                            });
+                        }
+                        "R" => module.relocations.push(number(1)?),
+                        "D" => module.definitions.push(PublicDefinition {
+                            name: name(1)?,
+                            address: number(2)?,
+                            relocatable: match fields.get(3).copied() {
+                                Some("R") => true,
+                                Some("A") => false,
+                                _ => return Err(error("expected R or A after the definition's address")),
+                            },
+                        }),
+                        "X" => module.external_references.push(ExternalReference {
+                            address: number(1)?,
+                            name: name(2)?,
+                            offset: fields
+                                .get(3)
+                                .and_then(|text| text.parse().ok())
+                                .ok_or_else(|| error("expected an offset after the symbol name"))?,
+                        }),
+                        "E" => module.entry = number(1)?,
+                        _ => return Err(error("unknown record type")),
+                    }
+                }
+            }
+        }
+
+        let mut module = module.ok_or(ObjectError {
+            line_number: 0,
+            message: String::from("missing header record"),
+        })?;
+        for entry in module.text.iter_mut() {
+            entry.relocatable = module.relocations.contains(&entry.address);
+        }
+        Ok(module)
+    }
+}
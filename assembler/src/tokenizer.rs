@@ -0,0 +1,73 @@
+use std::ops::Range;
+
+// a piece of a source line along with the columns it occupies
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Token<'a> {
+    pub text: &'a str,
+    pub columns: Range<usize>,
+}
+
+// the part of the line before a `;` comment. a `;` inside quotes, as in `C'A;B'`, does not start a comment
+pub(crate) fn strip_comment(line: &str) -> &str {
+    let mut in_quotes = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '\'' => in_quotes = !in_quotes,
+            ';' if !in_quotes => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+// splits a line into tokens on whitespace and commas, so `MOVER AREG, NUM ; load` gives
+// `MOVER`, `AREG` and `NUM`. quoted text stays inside its token
+pub(crate) fn tokenize(line: &str) -> Vec<Token<'_>> {
+    let code = strip_comment(line);
+    let mut tokens = Vec::new();
+    let mut start = None;
+    let mut in_quotes = false;
+
+    for (i, c) in code.char_indices() {
+        if in_quotes {
+            in_quotes = c != '\'';
+            continue;
+        }
+        if c.is_whitespace() || c == ',' {
+            if let Some(s) = start.take() {
+                tokens.push(Token { text: &code[s..i], columns: s..i });
+            }
+        } else {
+            start.get_or_insert(i);
+            in_quotes = c == '\'';
+        }
+    }
+    if let Some(s) = start {
+        tokens.push(Token { text: &code[s..], columns: s..code.len() });
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(line: &str) -> Vec<&str> {
+        tokenize(line).iter().map(|token| token.text).collect()
+    }
+
+    #[test]
+    fn splits_on_whitespace_and_commas() {
+        assert_eq!(texts("LOOP: MOVER AREG, NUM"), vec!["LOOP:", "MOVER", "AREG", "NUM"]);
+        assert_eq!(texts("  ADD\tAREG,='5'  "), vec!["ADD", "AREG", "='5'"]);
+        assert_eq!(tokenize("ADD AREG,NUM")[2].columns, 9..12);
+    }
+
+    #[test]
+    fn drops_comments_outside_quotes() {
+        assert_eq!(texts("STOP ; all done"), vec!["STOP"]);
+        assert_eq!(texts("; just a comment"), Vec::<&str>::new());
+        assert_eq!(texts("DC C'A;B, C' ; text"), vec!["DC", "C'A;B, C'"]);
+        assert_eq!(strip_comment("STOP ; all done"), "STOP ");
+    }
+}
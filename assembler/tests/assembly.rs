@@ -0,0 +1,955 @@
+// the assembler library driven the way the binary drives it: source lines in, tables, images and
+// diagnostics out
+
+use std::io;
+use std::ops::Range;
+
+use assembler::{
+    AddressingMode, Assembler, AssemblyOutput, ErrorType, InstructionSet, IntermediateCode, ObjectModule, Severity, ValueKind,
+};
+
+fn errors(output: &AssemblyOutput) -> Vec<(usize, Range<usize>, ErrorType)> {
+    output
+        .error_table
+        .iter()
+        .map(|e| (e.line_number(), e.columns(), e.error_type.clone()))
+        .collect()
+}
+
+fn words(output: &AssemblyOutput) -> Vec<(usize, usize)> {
+    output.machine_code_table.iter().map(|m| (m.address, m.word)).collect()
+}
+
+#[test]
+fn it_works() {
+    let source_lines: Vec<&str> = r#"
+START 300
+BEGIN: READ NUM
+LOOP: MOVEM AREG NUM
+PRINT NUM
+MUL AREG NUM
+COMP AREG HUNDRED
+BC LT LOOP
+STOP
+NUM: DS 2
+HUNDRED: DC 100
+END
+"#
+    .lines()
+    .map(str::trim)
+    .filter(|line| !line.is_empty())
+    .collect();
+    let output = Assembler::new().assemble(&source_lines);
+
+    assert!(output.error_table.is_empty());
+
+    assert_eq!(output.symbol_table.len(), 4);
+
+    assert_eq!(output.symbol_table[0].name, "BEGIN");
+    assert_eq!(output.symbol_table[0].address, 300);
+    assert!(output.symbol_table[0].defined);
+    assert!(!output.symbol_table[0].used);
+
+    assert_eq!(output.symbol_table[1].name, "NUM");
+    assert_eq!(output.symbol_table[1].address, 307);
+    assert!(output.symbol_table[1].defined);
+    assert!(output.symbol_table[1].used);
+
+    assert_eq!(output.symbol_table[2].name, "LOOP");
+    assert_eq!(output.symbol_table[2].address, 301);
+    assert!(output.symbol_table[2].defined);
+    assert!(output.symbol_table[2].used);
+
+    assert_eq!(output.symbol_table[3].name, "HUNDRED");
+    assert_eq!(output.symbol_table[3].address, 309);
+    assert!(output.symbol_table[3].defined);
+    assert!(output.symbol_table[3].used);
+
+    assert_eq!(output.intermediate_code_table.len(), 8);
+
+    let expected = [
+        IntermediateCode { line_number: 2, address: 300, opcode: 9, reg: None, value: ValueKind::Symbol(1), mode: AddressingMode::Direct },
+        IntermediateCode { line_number: 3, address: 301, opcode: 5, reg: Some(0), value: ValueKind::Symbol(1), mode: AddressingMode::Direct },
+        IntermediateCode { line_number: 4, address: 302, opcode: 10, reg: None, value: ValueKind::Symbol(1), mode: AddressingMode::Direct },
+        IntermediateCode { line_number: 5, address: 303, opcode: 3, reg: Some(0), value: ValueKind::Symbol(1), mode: AddressingMode::Direct },
+        IntermediateCode { line_number: 6, address: 304, opcode: 6, reg: Some(0), value: ValueKind::Symbol(3), mode: AddressingMode::Direct },
+        IntermediateCode { line_number: 7, address: 305, opcode: 7, reg: Some(0), value: ValueKind::Symbol(2), mode: AddressingMode::Direct },
+        IntermediateCode { line_number: 8, address: 306, opcode: 0, reg: None, value: ValueKind::Constant(0), mode: AddressingMode::Direct },
+        IntermediateCode { line_number: 10, address: 309, opcode: 12, reg: None, value: ValueKind::Constant(100), mode: AddressingMode::Direct },
+    ];
+
+    for (entry, expected_entry) in output.intermediate_code_table.iter().zip(expected.iter()) {
+        assert_eq!(entry.line_number, expected_entry.line_number);
+        assert_eq!(entry.address, expected_entry.address);
+        assert_eq!(entry.opcode, expected_entry.opcode);
+        assert_eq!(entry.reg, expected_entry.reg);
+        assert_eq!(entry.value, expected_entry.value);
+    }
+
+    // every symbolic operand, forward or backward, goes through the backpatch list
+    assert_eq!(output.backpatch_list.len(), 6);
+    assert_eq!(output.backpatch_list[5].ic_index, 5);
+    assert_eq!(output.backpatch_list[5].symbol, 2);
+}
+
+#[test]
+fn pass2_writes_the_simulator_image() {
+    let source_lines = ["START 100", "READ A", "PRINT B", "STOP", "A: DS 1", "B: DC 5", "END"];
+    let output = Assembler::new().assemble(&source_lines);
+    assert!(output.error_table.is_empty());
+
+    let mut image = Vec::new();
+    output.write_machine_code(&mut image).unwrap();
+    let expected = "100 090103\n101 100104\n102 000000\n104 000005\n-1 100";
+    assert_eq!(String::from_utf8(image).unwrap(), expected);
+}
+
+#[test]
+fn pass2_matches_hand_encoded_sum() {
+    let source_lines: Vec<&str> = r#"
+START 100
+READ A
+READ B
+MOVER BREG A
+ADD BREG B
+MOVEM BREG C
+PRINT C
+STOP
+A: DS 1
+B: DS 1
+C: DS 1
+END
+"#
+    .lines()
+    .map(str::trim)
+    .filter(|line| !line.is_empty())
+    .collect();
+    let output = Assembler::new().assemble(&source_lines);
+    assert!(output.error_table.is_empty());
+
+    let mut image = Vec::new();
+    output.write_machine_code(&mut image).unwrap();
+    let expected = "100 090107\n101 090108\n102 041107\n103 011108\n104 051109\n105 100109\n106 000000\n-1 100";
+    assert_eq!(String::from_utf8(image).unwrap(), expected);
+}
+
+#[test]
+fn pass2_resolves_backward_branch() {
+    let source_lines = ["START 200", "LOOP: PRINT NUM", "BC ANY LOOP", "NUM: DC 7", "END"];
+    let output = Assembler::new().assemble(&source_lines);
+
+    assert_eq!(words(&output), vec![(200, 100202), (201, 75200), (202, 7)]);
+}
+
+#[test]
+fn a_label_on_start_names_the_start_address() {
+    for start in ["PROG START 100", "PROG: START 100"] {
+        let output = Assembler::new().assemble([start, "BC ANY PROG", "END"]);
+        assert!(output.error_table.is_empty(), "{}", output.error_table[0]);
+        assert_eq!(output.symbol_table[0].address, 100);
+        assert_eq!(words(&output), vec![(100, 75100)]);
+        assert_eq!(output.object_module().relocate(200).unwrap()[0].word, 75200);
+    }
+}
+
+#[test]
+fn diagnostics_point_at_source() {
+    let source_lines = ["START 100", "LOOP: FOO AREG NUM", "PRINT TOTAL", "STOP", "END"];
+    let output = Assembler::with_file_name("test.asm").assemble(&source_lines);
+
+    assert_eq!(output.error_table.len(), 2);
+
+    let unknown = &output.error_table[0];
+    assert_eq!(unknown.error_type, ErrorType::UnknownMnemonic);
+    assert_eq!(unknown.severity, Severity::Error);
+    assert_eq!(unknown.line_number(), 2);
+    assert_eq!(unknown.columns(), 6..9);
+    assert_eq!(
+        unknown.to_string(),
+        "test.asm:2:7: error: unknown mnemonic\n    2 | LOOP: FOO AREG NUM\n      |       ^^^"
+    );
+
+    let undefined = &output.error_table[1];
+    assert_eq!(undefined.error_type, ErrorType::UndefinedSymbol("TOTAL".to_string()));
+    assert_eq!(undefined.line_number(), 3);
+    assert_eq!(undefined.columns(), 6..11);
+}
+
+#[test]
+fn malformed_directives_are_reported_not_fatal() {
+    let source_lines = ["START 3O0", "READ A", "STOP", "A: DS -2", "B: DS 2000", "C: DC x12", "D: DS", "END"];
+    let output = Assembler::new().assemble(&source_lines);
+
+    assert_eq!(
+        errors(&output),
+        vec![
+            (1, 6..9, ErrorType::InvalidNumber),
+            (4, 6..8, ErrorType::NegativeValue),
+            (5, 6..10, ErrorType::AddressOutOfRange(2001)),
+            (6, 6..9, ErrorType::InvalidNumber),
+            (7, 5..6, ErrorType::InvalidOperand),
+        ]
+    );
+}
+
+#[test]
+fn code_past_end_of_memory_is_reported() {
+    let source_lines = ["START 999", "STOP", "X: DC 1", "END"];
+    let output = Assembler::new().assemble(&source_lines);
+
+    assert_eq!(output.error_table.len(), 1);
+    assert_eq!(output.error_table[0].line_number(), 3);
+    assert_eq!(output.error_table[0].error_type, ErrorType::AddressOutOfRange(1000));
+}
+
+#[test]
+fn duplicate_label_points_at_both_definitions() {
+    let source_lines = ["START 100", "LOOP: PRINT X", "LOOP: BC ANY LOOP", "X: DC 1", "END"];
+    let output = Assembler::with_file_name("dup.asm").assemble(&source_lines);
+
+    assert_eq!(output.error_table.len(), 1);
+    let duplicate = &output.error_table[0];
+    assert_eq!(duplicate.error_type, ErrorType::DuplicateLabel("LOOP".to_string()));
+    assert_eq!(duplicate.line_number(), 3);
+    assert_eq!(duplicate.notes[0].location.line_number, 2);
+    assert_eq!(
+        duplicate.to_string(),
+        "dup.asm:3:1: error: label `LOOP` is defined more than once\n    3 | LOOP: BC ANY LOOP\n      | ^^^^\n\
+         dup.asm:2:1: note: first defined here\n    2 | LOOP: PRINT X\n      | ^^^^"
+    );
+
+    // the first definition wins
+    assert_eq!(output.symbol_table[0].address, 100);
+}
+
+#[test]
+fn unused_labels_warn_only_when_enabled() {
+    let source_lines = ["START 100", "BEGIN: PRINT X", "STOP", "X: DC 1", "SPARE: DC 2", "END"];
+
+    let quiet = Assembler::new().assemble(&source_lines);
+    assert!(quiet.warning_table.is_empty());
+
+    let mut assembler = Assembler::new();
+    assembler.enable_warnings();
+    let output = assembler.assemble(&source_lines);
+    assert!(output.error_table.is_empty());
+
+    let unused: Vec<(usize, ErrorType, Severity)> = output
+        .warning_table
+        .iter()
+        .map(|diagnostic| (diagnostic.line_number(), diagnostic.error_type.clone(), diagnostic.severity))
+        .collect();
+    assert_eq!(
+        unused,
+        vec![
+            (2, ErrorType::UnusedSymbol("BEGIN".to_string()), Severity::Warning),
+            (5, ErrorType::UnusedSymbol("SPARE".to_string()), Severity::Warning),
+        ]
+    );
+}
+
+#[test]
+fn json_output_has_tables_and_diagnostics() {
+    let source_lines = ["START 100", "READ N", "ADD AREG ='-2'", "PRINT M", "STOP", "N: DS 1", "END"];
+    let output = Assembler::with_file_name("prog.asm").assemble(&source_lines);
+
+    let json: serde_json::Value = serde_json::from_str(&output.to_json()).unwrap();
+    assert_eq!(json["file"], "prog.asm");
+    assert_eq!(json["entry"], 100);
+    assert_eq!(json["symbols"][0]["name"], "N");
+    assert_eq!(json["symbols"][0]["address"], 104);
+    assert_eq!(json["symbols"][0]["references"], serde_json::json!([2]));
+    assert_eq!(json["literals"][0], serde_json::json!({"value": -2, "address": 105}));
+    assert_eq!(
+        json["intermediate_code"][1],
+        serde_json::json!({
+            "line_number": 3,
+            "address": 101,
+            "opcode": 1,
+            "reg": 0,
+            "value": {"kind": "literal", "value": 0},
+            "mode": {"kind": "direct"}
+        })
+    );
+    assert_eq!(json["machine_code"][0], serde_json::json!({"address": 100, "word": 90104, "relocatable": true}));
+
+    let diagnostic = &json["diagnostics"][0];
+    assert_eq!(diagnostic["severity"], "error");
+    assert_eq!(diagnostic["code"], "undefined_symbol");
+    assert_eq!(diagnostic["argument"], "M");
+    assert_eq!(diagnostic["message"], "undefined symbol `M`");
+    assert_eq!(diagnostic["location"]["line_number"], 4);
+    assert_eq!(diagnostic["location"]["columns"], serde_json::json!({"start": 6, "end": 7}));
+}
+
+#[test]
+fn listing_shows_words_errors_and_cross_reference() {
+    let source_lines = ["START 100", "LOOP: READ N", "BOGUS N", "BC ANY LOOP", "N: DS 1", "END"];
+    // the source lines are not kept unless asked for
+    assert!(!Assembler::new().assemble(&source_lines).listing().contains("LOOP: READ N"));
+    let mut assembler = Assembler::new();
+    assembler.enable_listing();
+    let output = assembler.assemble(&source_lines);
+
+    let expected = "\
+LINE  LOC  WORD     SOURCE
+   1                START 100
+   2  100  090102   LOOP: READ N
+   3                BOGUS N
+                    ^^^^^ error: unknown mnemonic
+   4  101  075100   BC ANY LOOP
+   5  102           N: DS 1
+   6                END
+
+SYMBOL  ADDR  DEFINED  REFERENCES
+LOOP     100        2  4
+N        102        5  2
+";
+    assert_eq!(output.listing(), expected);
+}
+
+#[test]
+fn literals_are_pooled_at_ltorg_and_end() {
+    let source_lines = [
+        "START 100",
+        "MOVER AREG ='5'",
+        "ADD AREG ='1'",
+        "LTORG",
+        "MOVEM AREG X",
+        "SUB AREG ='5'",
+        "ADD AREG ='5'",
+        "STOP",
+        "X: DS 1",
+        "END",
+    ];
+    let output = Assembler::new().assemble(&source_lines);
+    assert!(output.error_table.is_empty());
+
+    let literals: Vec<(isize, Option<usize>)> =
+        output.literal_table.iter().map(|lit| (lit.value, lit.address)).collect();
+    assert_eq!(literals, vec![(5, Some(102)), (1, Some(103)), (5, Some(109))]);
+
+    assert_eq!(
+        words(&output),
+        vec![
+            (100, 40102),
+            (101, 10103),
+            (102, 5),
+            (103, 1),
+            (104, 50108),
+            (105, 20109),
+            (106, 10109),
+            (107, 0),
+            (109, 5),
+        ]
+    );
+}
+
+#[test]
+fn malformed_literal_is_reported() {
+    let source_lines = ["ADD AREG ='x'", "ADD AREG =5", "END"];
+    let output = Assembler::new().assemble(&source_lines);
+
+    assert_eq!(errors(&output), vec![(1, 9..13, ErrorType::InvalidLiteral), (2, 9..11, ErrorType::InvalidLiteral)]);
+}
+
+#[test]
+fn origin_equ_and_address_expressions() {
+    let source_lines = [
+        "START 100",
+        "ARR: DS 3",
+        "SIZE EQU 3",
+        "TOP: EQU ARR+2",
+        "MOVER AREG ARR+1",
+        "MOVEM AREG TOP",
+        "BC ANY LOOP-1",
+        "ORIGIN 200",
+        "STOP",
+        "LOOP: PRINT ARR",
+        "ORIGIN LOOP+SIZE",
+        "X: DC 1",
+        "END",
+    ];
+    let output = Assembler::new().assemble(&source_lines);
+    assert!(output.error_table.is_empty());
+
+    let size = output.symbol_table.iter().find(|sym| sym.name == "SIZE").unwrap();
+    assert_eq!(size.address, 3);
+    let top = output.symbol_table.iter().find(|sym| sym.name == "TOP").unwrap();
+    assert_eq!(top.address, 102);
+
+    assert_eq!(words(&output), vec![(103, 40101), (104, 50102), (105, 75200), (200, 0), (201, 100100), (204, 1)]);
+}
+
+#[test]
+fn origin_cannot_move_back_over_words() {
+    let source_lines = ["START 100", "STOP", "ORIGIN 100", "PRINT A", "A: DC 1", "END"];
+    let output = Assembler::new().assemble(&source_lines);
+    assert_eq!(errors(&output), vec![(4, 0..7, ErrorType::AddressInUse(100))]);
+    assert_eq!(words(&output), vec![(100, 0), (101, 1)]);
+}
+
+#[test]
+fn bad_expressions_are_reported() {
+    let source_lines = [
+        "START 100",
+        "ORIGIN LATER",
+        "A EQU",
+        "   BC ANY X-200   ; back",
+        "ADD AREG X+Y",
+        "STOP",
+        "X: DC 1",
+        "Y: DC 2",
+        "LATER: DC 3",
+        "END",
+    ];
+    let output = Assembler::new().assemble(&source_lines);
+
+    assert_eq!(
+        errors(&output),
+        vec![
+            (2, 7..12, ErrorType::ForwardReference("LATER".to_string())),
+            (3, 5..6, ErrorType::InvalidOperand),
+            (4, 10..15, ErrorType::NegativeValue),
+            (5, 9..12, ErrorType::InvalidExpression),
+        ]
+    );
+    // found in pass 2, still pointing at the operand rather than the whole line
+    assert_eq!(
+        output.error_table[2].to_string(),
+        "<source>:4:11: error: value must not be negative\n    4 |    BC ANY X-200   ; back\n      |           ^^^^^"
+    );
+}
+
+#[test]
+fn expression_overflow_is_reported() {
+    let source_lines = [
+        "START 100",
+        "A EQU 9223372036854775807",
+        "B EQU A+A",
+        "ADD AREG X+9223372036854775807+9223372036854775807",
+        "ORIGIN 18446744073709551615",
+        "SUB AREG A+1",
+        "X: DC 1",
+        "END",
+    ];
+    let output = Assembler::new().assemble(&source_lines);
+
+    assert_eq!(
+        errors(&output),
+        vec![
+            (3, 6..9, ErrorType::InvalidExpression),
+            (4, 9..50, ErrorType::InvalidExpression),
+            (5, 7..27, ErrorType::InvalidExpression),
+            (6, 9..12, ErrorType::InvalidExpression),
+        ]
+    );
+}
+
+#[test]
+fn free_form_source_with_comments_and_commas() {
+    let source_lines = [
+        "; sum of two numbers",
+        "start 100",
+        "read A            ; first",
+        "Read B",
+        "LOOP mover breg, A",
+        "  ADD BREG,B",
+        "MOVEM BReg , C",
+        "PRINT C ; done",
+        "stop",
+        "A DS 1",
+        "B: ds 1",
+        "C DS 1",
+        "END",
+    ];
+    let output = Assembler::new().assemble(&source_lines);
+    assert!(output.error_table.is_empty());
+
+    assert_eq!(
+        words(&output),
+        vec![(100, 90107), (101, 90108), (102, 41107), (103, 11108), (104, 51109), (105, 100109), (106, 0)]
+    );
+    assert_eq!(output.symbol_table.iter().find(|sym| sym.name == "LOOP").unwrap().address, 102);
+}
+
+#[test]
+fn operands_are_checked_against_the_instruction() {
+    let source_lines = [
+        "START 100",
+        "BC NEVER L",
+        "ADD AREG",
+        "MOVER AREG BREG",
+        "PRINT",
+        "STOP NOW",
+        "L: MOVEM CREG, X Y Z",
+        "X: DC 1 2",
+        "BC ANY",
+        "END",
+    ];
+    let output = Assembler::new().assemble(&source_lines);
+    assert_eq!(
+        errors(&output),
+        vec![
+            (2, 3..8, ErrorType::UnknownConditionCode(String::from("NEVER"))),
+            (3, 8..9, ErrorType::MissingOperand),
+            (4, 11..15, ErrorType::RegisterWhereMemoryExpected(String::from("BREG"))),
+            (5, 5..6, ErrorType::MissingOperand),
+            (6, 5..8, ErrorType::TooManyOperands),
+            (7, 17..20, ErrorType::TooManyOperands),
+            (8, 8..9, ErrorType::TooManyOperands),
+            (9, 6..7, ErrorType::MissingLabel),
+        ]
+    );
+}
+
+#[test]
+fn directives_reject_extra_operands() {
+    let source_lines = [
+        "START 100 200",
+        "N EQU 5 6",
+        "V SET 1 2",
+        "IF 1 EQ 1 JUNK",
+        "ELSE NOW",
+        "ENDIF IF",
+        "ORIGIN 110 120",
+        "LTORG AGAIN",
+        "END FOO BAR",
+    ];
+    let output = Assembler::new().assemble(&source_lines);
+    assert_eq!(
+        errors(&output),
+        vec![
+            (1, 10..13, ErrorType::TooManyOperands),
+            (2, 8..9, ErrorType::TooManyOperands),
+            (3, 8..9, ErrorType::TooManyOperands),
+            (4, 10..14, ErrorType::TooManyOperands),
+            (5, 5..8, ErrorType::TooManyOperands),
+            (6, 6..8, ErrorType::TooManyOperands),
+            (7, 11..14, ErrorType::TooManyOperands),
+            (8, 6..11, ErrorType::TooManyOperands),
+            (9, 4..11, ErrorType::TooManyOperands),
+        ]
+    );
+}
+
+#[test]
+fn immediate_and_indexed_operands() {
+    let source_lines = [
+        "START 100",
+        "SIZE EQU 3",
+        "MOVER BREG #0",
+        "MOVER CREG #0",
+        "LOOP: ADD CREG ARR(BREG)",
+        "ADD BREG #1",
+        "COMP BREG #SIZE",
+        "BC LT LOOP",
+        "MOVEM CREG SUM",
+        "PRINT ARR+1(AREG)",
+        "STOP",
+        "ARR: DC '4,5,6'",
+        "SUM: DS 1",
+        "END",
+    ];
+    let output = Assembler::new().assemble(&source_lines);
+    assert!(output.error_table.is_empty(), "{}", output.error_table[0]);
+
+    let words: Vec<usize> = output.machine_code_table.iter().map(|m| m.word).collect();
+    assert_eq!(
+        words,
+        vec![1041000, 1042000, 3012109, 1011001, 1061003, 70102, 52112, 2100110, 0, 4, 5, 6]
+    );
+    assert_eq!(output.intermediate_code_table[2].mode, AddressingMode::Indexed(1));
+    // an immediate address still moves with the program, an immediate number does not
+    let object = output.object_module();
+    assert_eq!(object.relocate(200).unwrap()[2].word, 3012209);
+    assert_eq!(object.relocate(200).unwrap()[4].word, 1061003);
+
+    let source_lines = [
+        "MOVEM AREG #5",
+        "READ #N",
+        "BC ANY #0",
+        "ADD AREG #1000",
+        "ADD AREG 1000(BREG)",
+        "ADD AREG N(XREG)",
+        "ADD AREG #='5'",
+        "N: DS 1",
+    ];
+    let output = Assembler::new().assemble(&source_lines);
+    assert_eq!(
+        errors(&output),
+        vec![
+            (1, 11..13, ErrorType::ImmediateNotAllowed),
+            (2, 5..7, ErrorType::ImmediateNotAllowed),
+            (3, 7..9, ErrorType::ImmediateNotAllowed),
+            (4, 10..14, ErrorType::ImmediateOutOfRange(1000)),
+            (5, 9..13, ErrorType::AddressOutOfRange(1000)),
+            (6, 11..15, ErrorType::InvalidValue),
+            (7, 9..14, ErrorType::InvalidOperand),
+        ]
+    );
+}
+
+#[test]
+fn subroutines_and_the_stack() {
+    // BREG = AREG!, recursing on AREG-1
+    let source_lines = [
+        "START 100",
+        "READ N",
+        "MOVER AREG N",
+        "CALL FACT",
+        "MOVEM BREG N",
+        "PRINT N",
+        "STOP",
+        "FACT: COMP AREG #1",
+        "BC GT REC",
+        "MOVER BREG #1",
+        "RET",
+        "REC: PUSH AREG",
+        "SUB AREG #1",
+        "CALL FACT",
+        "POP AREG",
+        "MOVEM AREG T",
+        "MUL BREG T",
+        "RET",
+        "N: DS 1",
+        "T: DS 1",
+        "END",
+    ];
+    let output = Assembler::new().assemble(&source_lines);
+    assert!(output.error_table.is_empty(), "{}", output.error_table[0]);
+
+    let words: Vec<usize> = output.machine_code_table.iter().map(|m| m.word).collect();
+    assert_eq!(&words[2..4], &[130106, 51117]);
+    assert_eq!(&words[9..14], &[140000, 150000, 1020001, 130106, 160000]);
+
+    let source_lines = ["PUSH", "POP XREG", "CALL #100", "RET AREG", "PUSH AREG N"];
+    let output = Assembler::new().assemble(&source_lines);
+    assert_eq!(
+        errors(&output),
+        vec![
+            (1, 4..5, ErrorType::MissingOperand),
+            (2, 4..8, ErrorType::InvalidValue),
+            (3, 5..9, ErrorType::ImmediateNotAllowed),
+            (4, 4..8, ErrorType::TooManyOperands),
+            (5, 10..11, ErrorType::TooManyOperands),
+        ]
+    );
+}
+
+#[test]
+fn streamed_source_keeps_line_numbers() {
+    let source = "\n    START 100\n\n; totals\n    READ N\n    PRINT M\r\n    STOP\nN:  DS 1\n\n    END\n";
+    let output = Assembler::new().assemble_reader(io::Cursor::new(source)).unwrap();
+
+    let undefined = &output.error_table[0];
+    assert_eq!(undefined.error_type, ErrorType::UndefinedSymbol(String::from("M")));
+    assert_eq!(undefined.line_number(), 6);
+    assert_eq!(undefined.columns(), 10..11);
+    let lines: Vec<usize> = output.intermediate_code_table.iter().map(|entry| entry.line_number).collect();
+    assert_eq!(lines, vec![5, 6, 7]);
+
+    // fed a line at a time, the result is the same
+    let mut incremental = Assembler::new();
+    for line in source.lines() {
+        incremental.pass1_line(line);
+    }
+    assert_eq!(incremental.finish().error_table, output.error_table);
+}
+
+#[test]
+fn session_assembles_units_independently_or_shared() {
+    let first = ["START 100", "PRINT X", "STOP", "X: DC 5", "END"];
+    let second = ["START 200", "PRINT X", "STOP", "END"];
+
+    // nothing carries over from one unit to the next
+    let mut assembler = Assembler::new();
+    assembler.define("SIZE", 3);
+    let once = assembler.assemble(&first);
+    let again = assembler.assemble(&first);
+    assert!(again.error_table.is_empty());
+    assert_eq!(again.machine_code_table, once.machine_code_table);
+    assert_eq!(again.symbol_table.len(), 2);
+    let undefined = assembler.assemble(&second);
+    assert_eq!(undefined.error_table[0].error_type, ErrorType::UndefinedSymbol(String::from("X")));
+
+    // a unit that was fed partly and then reset leaves no trace either
+    assembler.pass1_line("START 500");
+    assembler.pass1_line("Y: DC 1");
+    assembler.reset();
+    assert_eq!(assembler.finish().symbol_table.len(), 1);
+
+    let mut shared = Assembler::new();
+    shared.share_namespace();
+    let defining = shared.assemble(&first);
+    assert!(defining.object_module().to_string().contains("D X 102 R\n"));
+    let output = shared.assemble(&second);
+    assert!(output.error_table.is_empty());
+    assert_eq!(output.machine_code_table[0].word, 100000);
+    assert!(output.object_module().to_string().contains("X 200 X 0\n"));
+
+    let duplicate = ["START 300", "X: DC 1", "END"];
+    let output = shared.assemble(&duplicate);
+    assert_eq!(output.error_table[0].error_type, ErrorType::DuplicateLabel(String::from("X")));
+}
+
+#[test]
+fn missing_operand_is_reported_before_comment() {
+    let source_lines = ["BC LT   ; branch"];
+    let output = Assembler::new().assemble(&source_lines);
+
+    assert_eq!(output.error_table[0].error_type, ErrorType::MissingLabel);
+    assert_eq!(output.error_table[0].columns(), 5..6);
+}
+
+#[test]
+fn object_module_records_relocations() {
+    let source_lines = [
+        "START 100",
+        "SIZE EQU 5",
+        "LOOP: READ N",
+        "ADD AREG ='1'",
+        "SUB AREG SIZE",
+        "MULT: MUL AREG 7",
+        "BC ANY LOOP",
+        "N: DS 2",
+        "END",
+    ];
+    let output = Assembler::with_file_name("data/loop.asm").assemble(&source_lines);
+
+    let object = output.object_module();
+    let expected = "\
+H LOOP 100 008
+T 100 090105
+T 101 010107
+T 102 020005
+T 103 030007
+T 104 075100
+T 107 000001
+R 100
+R 101
+R 104
+E 100
+";
+    assert_eq!(object.to_string(), expected);
+    assert_eq!(expected.parse::<ObjectModule>().unwrap(), object);
+
+    let moved: Vec<(usize, usize)> = object.relocate(500).unwrap().iter().map(|m| (m.address, m.word)).collect();
+    assert_eq!(
+        moved,
+        vec![(500, 90505), (501, 10507), (502, 20005), (503, 30007), (504, 75500), (507, 1)]
+    );
+    assert_eq!(object.relocated_entry(500), Some(500));
+    assert!(object.relocate(995).is_err());
+}
+
+#[test]
+fn malformed_object_is_rejected() {
+    let error = "T 100 090105\n".parse::<ObjectModule>().unwrap_err();
+    assert_eq!(error.line_number, 1);
+    assert!("H X 100 1\nT 100 abc\n".parse::<ObjectModule>().is_err());
+    assert!("H X 100 1\nQ 1\n".parse::<ObjectModule>().is_err());
+    assert!("H X 100 1\nT 100 10000000\n".parse::<ObjectModule>().is_err());
+}
+
+#[test]
+fn assembles_for_a_loaded_instruction_set() {
+    let description = "\
+INSTRUCTION HALT 0 none 1
+INSTRUCTION LOAD 4 reg-mem 1
+INSTRUCTION JUMP 7 cond-mem 2
+INSTRUCTION INC 13 reg-mem 1
+INSTRUCTION WORD 12 constant 1
+INSTRUCTION SPACE 11 storage 1
+REGISTER AREG 0
+REGISTER EREG 4
+CONDITION ALWAYS 5
+";
+    let instruction_set: InstructionSet = description.parse().unwrap();
+    let source_lines = ["START 10", "TOP: INC EREG ='1'", "JUMP ALWAYS TOP", "LOAD AREG N", "HALT", "N: WORD 7", "END"];
+    let mut assembler = Assembler::new();
+    assembler.use_instruction_set(instruction_set);
+    let output = assembler.assemble(&source_lines);
+    assert!(output.error_table.is_empty());
+
+    // JUMP takes two words, so LOAD lands at 13
+    assert_eq!(words(&output), vec![(10, 134016), (11, 75010), (13, 40015), (14, 0), (15, 7), (16, 1)]);
+
+    // the SMAC0 mnemonics are gone
+    let mut assembler = Assembler::new();
+    assembler.use_instruction_set(description.parse().unwrap());
+    let output = assembler.assemble(&[String::from("STOP")]);
+    assert_eq!(output.error_table[0].error_type, ErrorType::UnknownMnemonic);
+}
+
+#[test]
+fn conditional_assembly_with_defines_and_set() {
+    let source_lines = [
+        "START 100",
+        "COUNT SET 1",
+        "IF DEBUG",
+        "PRINT N",
+        "COUNT SET COUNT+1",
+        "ELSE",
+        "IF 0",
+        "IF UNDEFINED EQ 1",
+        "NEVER: STOP",
+        "ENDIF",
+        "ENDIF",
+        "ENDIF",
+        "IF COUNT GE 2",
+        "MOVER AREG COUNT",
+        "ENDIF",
+        "STOP",
+        "N: DC 5",
+        "END",
+    ];
+    let assemble = |debug: usize| {
+        let mut assembler = Assembler::new();
+        assembler.define("DEBUG", debug);
+        let output = assembler.assemble(&source_lines);
+        assert!(output.error_table.is_empty(), "{}", output.error_table[0]);
+        words(&output)
+    };
+
+    // the SET variable is 2 by the time MOVER uses it. skipped blocks are not evaluated, so UNDEFINED is fine
+    assert_eq!(assemble(1), vec![(100, 100103), (101, 40002), (102, 0), (103, 5)]);
+    assert_eq!(assemble(0), vec![(100, 0), (101, 5)]);
+
+    let source_lines = ["IF 1 XX 2", "ENDIF", "ELSE", "DEBUG EQU 1", "IF 1"];
+    let mut assembler = Assembler::new();
+    assembler.define("DEBUG", 0);
+    let output = assembler.assemble(&source_lines);
+    assert_eq!(
+        errors(&output),
+        vec![
+            (1, 5..7, ErrorType::InvalidComparison),
+            (3, 0..4, ErrorType::UnmatchedConditional(String::from("ELSE"))),
+            (4, 0..5, ErrorType::DuplicateLabel(String::from("DEBUG"))),
+            (5, 0..2, ErrorType::UnterminatedIf),
+        ]
+    );
+}
+
+#[test]
+fn set_after_use_and_labels_on_conditionals() {
+    let source_lines = ["START 100", "MOVER AREG C", "C SET 5", "C SET 7", "STOP", "END"];
+    let output = Assembler::new().assemble(&source_lines);
+    assert_eq!(errors(&output), vec![(2, 11..12, ErrorType::ForwardReference(String::from("C")))]);
+
+    // a label on IF, ELSE or ENDIF is defined when the lines around the block are assembled
+    let source_lines = [
+        "START 100",
+        "L: IF 0",
+        "N: IF 1",
+        "ENDIF",
+        "E: ELSE",
+        "ENDIF",
+        "BC ANY L",
+        "BC ANY E",
+        "STOP",
+        "END",
+    ];
+    let output = Assembler::new().assemble(&source_lines);
+    assert!(output.error_table.is_empty(), "{}", output.error_table[0]);
+    assert_eq!(words(&output), vec![(100, 75100), (101, 75100), (102, 0)]);
+    assert!(output.symbol_table.iter().all(|symbol| symbol.name != "N"));
+}
+
+#[test]
+fn multi_word_and_character_constants() {
+    let source_lines = [
+        "START 200",
+        "SIZE EQU 2",
+        "TABLE: DC '5, 10,15'",
+        "NAME: DC C'Hi!'",
+        "BUF: DS SIZE+1",
+        "LAST: DC 7",
+        "END",
+    ];
+    let output = Assembler::new().assemble(&source_lines);
+    assert!(output.error_table.is_empty());
+
+    assert_eq!(words(&output), vec![(200, 5), (201, 10), (202, 15), (203, 72), (204, 105), (205, 33), (209, 7)]);
+    let addresses: Vec<usize> = output.symbol_table.iter().map(|s| s.address).collect();
+    assert_eq!(addresses, vec![2, 200, 203, 206, 209]);
+
+    let source_lines = ["DC '5,x'", "DC C''", "DC '1,2", "DS LATER", "DS 1-3", "LATER: STOP", "A: DS 1", "B: DS A"];
+    let output = Assembler::new().assemble(&source_lines);
+    assert_eq!(
+        errors(&output),
+        vec![
+            (1, 6..7, ErrorType::InvalidNumber),
+            (2, 3..6, ErrorType::InvalidConstant),
+            (3, 3..7, ErrorType::InvalidConstant),
+            (4, 3..8, ErrorType::ForwardReference(String::from("LATER"))),
+            (5, 3..6, ErrorType::NegativeValue),
+            (8, 6..7, ErrorType::InvalidExpression),
+        ]
+    );
+}
+
+#[test]
+fn negative_constants_are_stored_in_tens_complement() {
+    let source_lines = [
+        "START 100",
+        "MOVER AREG ='-3'",
+        "ADD AREG MINUS",
+        "STOP",
+        "MINUS: DC -1",
+        "LIST: DC '2,-2'",
+        "END",
+    ];
+    let output = Assembler::new().assemble(&source_lines);
+    assert!(output.error_table.is_empty());
+
+    assert_eq!(
+        words(&output),
+        vec![(100, 40106), (101, 10103), (102, 0), (103, 999999), (104, 2), (105, 999998), (106, 999997)]
+    );
+    assert_eq!(assembler::decode_word(999997), -3);
+    assert_eq!(assembler::encode_word(499999), Some(499999));
+    assert_eq!(assembler::encode_word(-500000), Some(500000));
+
+    let source_lines = ["DC 500000", "DC -500001", "ADD AREG ='600000'", "DC x12"];
+    let output = Assembler::new().assemble(&source_lines);
+    assert_eq!(
+        errors(&output),
+        vec![
+            (1, 3..9, ErrorType::WordOutOfRange(500000)),
+            (2, 3..10, ErrorType::WordOutOfRange(-500001)),
+            (3, 9..18, ErrorType::WordOutOfRange(600000)),
+            (4, 3..6, ErrorType::InvalidNumber),
+        ]
+    );
+}
+
+#[test]
+fn entry_and_extrn_directives() {
+    let source_lines = ["START 0", "ENTRY MAIN", "EXTRN TOTAL", "MAIN: ADD AREG TOTAL+1", "STOP", "END"];
+    let output = Assembler::with_file_name("main.asm").assemble(&source_lines);
+    assert!(output.error_table.is_empty());
+
+    // the offset goes in the X record, the mem field is left for the linker
+    let object = output.object_module();
+    assert_eq!(object.to_string(), "H MAIN 000 002\nT 000 010000\nT 001 000000\nD MAIN 000 R\nX 000 TOTAL 1\nE 000\n");
+    assert_eq!(object.to_string().parse::<ObjectModule>().unwrap(), object);
+
+    // only the linker knows whether a negative offset stays inside memory
+    let output = Assembler::new().assemble(["EXTRN X", "READ X-1", "END"]);
+    assert!(output.error_table.is_empty());
+    assert!(output.object_module().to_string().contains("X 000 X -1\n"));
+
+    let source_lines = ["EXTRN X", "X: DC 1", "ENTRY MISSING", "EXTRN Y", "ENTRY Y", "ENTRY Z", "EXTRN Z", "END"];
+    let output = Assembler::new().assemble(&source_lines);
+    let errors: Vec<_> = output.error_table.iter().map(|e| e.error_type.clone()).collect();
+    assert_eq!(
+        errors,
+        vec![
+            ErrorType::ExternalDefinedLocally(String::from("X")),
+            ErrorType::UndefinedSymbol(String::from("MISSING")),
+            ErrorType::ExternalEntry(String::from("Y")),
+            ErrorType::ExternalEntry(String::from("Z")),
+        ]
+    );
+}
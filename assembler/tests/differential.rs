@@ -0,0 +1,306 @@
+// differential tests: random valid SMAC0 programs are assembled, loaded into the simulator and run,
+// and what they print is compared with a reference interpreter that runs the intermediate code
+// directly. a mismatch points at the encoding in pass 2 or the decoding in the simulator.
+//
+// programs come from a seeded generator, a failure prints the seed and the source to replay it
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{self, Cursor, Write};
+use std::rc::Rc;
+
+use assembler::{decode_word, AddressingMode, Assembler, AssemblyOutput, ValueKind, WORD_SIZE};
+use smac0_simulator::SMAC0;
+
+const CASES: u64 = 500;
+// forward branches only, and subroutines only call the ones after them, so every program finishes
+// well within this many steps
+const STEP_LIMIT: usize = 1000;
+// words in the simulator's default stack region, 900..1000
+const STACK_SIZE: usize = 100;
+
+const REGISTERS: [&str; 4] = ["AREG", "BREG", "CREG", "DREG"];
+const CONDITIONS: [&str; 6] = ["LT", "LE", "EQ", "GT", "GE", "ANY"];
+
+// xorshift64*, enough to spread programs around without pulling in a crate
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+        items[self.below(items.len())]
+    }
+}
+
+struct Program {
+    source: Vec<String>,
+    input: Vec<isize>,
+}
+
+fn generate(rng: &mut Rng) -> Program {
+    let mut source = vec![format!("START {}", rng.below(500))];
+
+    // variables are one or two words, so `V0+1` style operands get exercised too
+    let variables: Vec<(String, usize)> = (0..1 + rng.below(4)).map(|i| (format!("V{i}"), 1 + rng.below(2))).collect();
+    let variable = |rng: &mut Rng| {
+        let (name, size) = &variables[rng.below(variables.len())];
+        match rng.below(*size) {
+            0 => name.clone(),
+            offset => format!("{name}+{offset}"),
+        }
+    };
+    // a variable that is only read is now and then indexed. registers hold small values or products
+    // of them, so many indexed addresses land outside memory or in the code and the program is skipped
+    let read_variable = |rng: &mut Rng| {
+        let name = variable(rng);
+        match rng.below(8) {
+            0 => format!("{name}({})", rng.pick(&REGISTERS)),
+            _ => name,
+        }
+    };
+
+    // a literal, an immediate number or a variable
+    let operand = |rng: &mut Rng| match rng.below(4) {
+        0 => format!("='{}'", small(rng)),
+        1 => format!("#{}", rng.below(20)),
+        _ => read_variable(rng),
+    };
+
+    let count = 3 + rng.below(25);
+    let subroutines = rng.below(4);
+    // values pushed so far in source order. a branch can still skip a PUSH, then the POP underflows
+    // and the program is skipped
+    let mut depth = 0;
+    for i in 0..count {
+        let register = rng.pick(&REGISTERS);
+        let instruction = match rng.below(12) {
+            0 => format!("READ {}", variable(rng)),
+            1 => format!("PRINT {}", read_variable(rng)),
+            2 => format!("MOVEM {register} {}", variable(rng)),
+            3 => format!("BC {} L{}", rng.pick(&CONDITIONS), i + 1 + rng.below(count - i)),
+            // a nonzero literal keeps most divisions from faulting
+            4 => format!("DIV {register} ='{}'", rng.pick(&["-7", "-2", "1", "3", "9"])),
+            5 if subroutines > 0 => format!("CALL S{}", rng.below(subroutines)),
+            6 if depth > 0 && rng.below(2) == 0 => {
+                depth -= 1;
+                format!("POP {register}")
+            }
+            6 => {
+                depth += 1;
+                format!("PUSH {register}")
+            }
+            _ => {
+                let mnemonic = rng.pick(&["ADD", "SUB", "MUL", "MUL", "MOVER", "COMP"]);
+                format!("{mnemonic} {register} {}", operand(rng))
+            }
+        };
+        source.push(format!("L{i}: {instruction}"));
+    }
+    source.push(format!("L{count}: STOP"));
+
+    // a subroutine pops what it pushes, so RET always finds the return address on top
+    for s in 0..subroutines {
+        let mut body = Vec::new();
+        let saves = rng.below(2) == 0;
+        if saves {
+            body.push(format!("PUSH {}", rng.pick(&REGISTERS)));
+        }
+        for _ in 0..1 + rng.below(4) {
+            let register = rng.pick(&REGISTERS);
+            body.push(match rng.below(5) {
+                0 if s + 1 < subroutines => format!("CALL S{}", s + 1 + rng.below(subroutines - s - 1)),
+                1 => format!("PRINT {}", read_variable(rng)),
+                2 => format!("MOVEM {register} {}", variable(rng)),
+                _ => {
+                    let mnemonic = rng.pick(&["ADD", "SUB", "MUL", "MOVER"]);
+                    format!("{mnemonic} {register} {}", operand(rng))
+                }
+            });
+        }
+        if saves {
+            body.push(format!("POP {}", rng.pick(&REGISTERS)));
+        }
+        body.push(String::from("RET"));
+        source.push(format!("S{s}: {}", body[0]));
+        source.extend(body.into_iter().skip(1));
+    }
+
+    for (name, size) in &variables {
+        match rng.below(2) {
+            0 => source.push(format!("{name}: DS {size}")),
+            _ => {
+                source.push(format!("{name}: DC {}", small(rng)));
+                if *size == 2 {
+                    source.push(format!("DC {}", small(rng)));
+                }
+            }
+        }
+    }
+    source.push(String::from("END"));
+
+    let input = (0..count).map(|_| small(rng)).collect();
+    Program { source, input }
+}
+
+// -20 through 19. repeated MUL pushes values past the edge of a word, so wrapping gets exercised too
+fn small(rng: &mut Rng) -> isize {
+    rng.below(40) as isize - 20
+}
+
+// the value a result stands for once it has been cut down to a word
+fn wrap(value: isize) -> isize {
+    decode_word(value.rem_euclid(WORD_SIZE as isize) as usize)
+}
+
+// runs the intermediate code the assembler built in pass 1, resolving operands from its tables.
+// None when the program divides by zero, does not stop, indexes outside its data or pops an empty
+// stack, those programs are skipped
+fn reference_run(output: &AssemblyOutput, start: usize, input: &[isize]) -> Option<Vec<isize>> {
+    let offsets: HashMap<usize, isize> = output
+        .backpatch_list
+        .iter()
+        .map(|patch| (patch.ic_index, patch.offset))
+        .collect();
+    let operand = |index: usize| match output.intermediate_code_table[index].value {
+        ValueKind::Constant(value) => value,
+        ValueKind::Literal(literal) => output.literal_table[literal].address.unwrap(),
+        ValueKind::Symbol(symbol) => (output.symbol_table[symbol].address as isize + offsets[&index]) as usize,
+    };
+
+    // values rather than stored words
+    let mut memory = [0isize; 1000];
+    let mut code = HashMap::new();
+    for (index, entry) in output.intermediate_code_table.iter().enumerate() {
+        if entry.opcode == 12 {
+            memory[entry.address] = decode_word(operand(index));
+        } else {
+            code.insert(entry.address, index);
+        }
+    }
+
+    let mut registers = [0isize; 4];
+    let mut conditions = [false; 6];
+    // return addresses and pushed registers, overflowing it is a fault like dividing by zero
+    let mut stack = Vec::new();
+    let mut input = input.iter();
+    let mut printed = Vec::new();
+    let mut pc = start;
+    for _ in 0..STEP_LIMIT {
+        let &index = code.get(&pc)?;
+        let entry = &output.intermediate_code_table[index];
+        let (reg, mut mem) = (entry.reg.unwrap_or(0), operand(index));
+        if let AddressingMode::Indexed(index_register) = entry.mode {
+            let address = mem as isize + registers[index_register];
+            // the reference keeps code apart from data, so reading an instruction word means nothing here
+            if !(0..1000).contains(&address) || code.contains_key(&(address as usize)) {
+                return None;
+            }
+            mem = address as usize;
+        }
+        let value = match entry.mode {
+            AddressingMode::Immediate => mem as isize,
+            _ => memory[mem],
+        };
+        pc += 1;
+        match entry.opcode {
+            0 => return Some(printed),
+            1 => registers[reg] = wrap(registers[reg] + value),
+            2 => registers[reg] = wrap(registers[reg] - value),
+            3 => registers[reg] = wrap(registers[reg] * value),
+            8 => registers[reg] = wrap(registers[reg].checked_div(value)?),
+            4 => registers[reg] = value,
+            5 => memory[mem] = registers[reg],
+            6 => {
+                let (a, b) = (registers[reg], value);
+                conditions = [a < b, a <= b, a == b, a > b, a >= b, true];
+            }
+            7 => {
+                if reg == 5 || conditions[reg] {
+                    pc = mem;
+                }
+            }
+            9 => memory[mem] = *input.next()?,
+            10 => printed.push(value),
+            13 | 15 if stack.len() == STACK_SIZE => return None,
+            13 => {
+                stack.push(pc as isize);
+                pc = mem;
+            }
+            14 => pc = usize::try_from(stack.pop()?).ok()?,
+            15 => stack.push(registers[reg]),
+            16 => registers[reg] = stack.pop()?,
+            _ => return None,
+        }
+    }
+    None
+}
+
+// a writer the test can read back after handing it to the simulator
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn simulator_run(image: String, input: &[isize]) -> Result<Vec<isize>, String> {
+    let input: String = input.iter().map(|value| format!("{value}\n")).collect();
+    let output = SharedBuffer::default();
+    let mut smac = SMAC0::with_io(Box::new(Cursor::new(input)), Box::new(output.clone()));
+    smac.parse_file(image);
+    smac.execute().map_err(|e| e.to_string())?;
+
+    let printed = String::from_utf8(output.0.borrow().clone()).unwrap();
+    Ok(printed
+        .lines()
+        .filter_map(|line| line.strip_prefix("printing: "))
+        .map(|value| value.parse().unwrap())
+        .collect())
+}
+
+#[test]
+fn simulator_agrees_with_intermediate_code() {
+    let mut compared = 0;
+    for seed in 0..CASES {
+        let program = generate(&mut Rng::new(seed));
+        let output = Assembler::new().assemble(&program.source);
+        assert!(
+            output.error_table.is_empty(),
+            "seed {seed}: {}\n{}",
+            output.error_table[0],
+            program.source.join("\n")
+        );
+
+        let start = program.source[0]["START ".len()..].parse().unwrap();
+        let Some(expected) = reference_run(&output, start, &program.input) else {
+            continue;
+        };
+        let mut image = Vec::new();
+        output.write_machine_code(&mut image).unwrap();
+        let actual = simulator_run(String::from_utf8(image).unwrap(), &program.input);
+        assert_eq!(actual, Ok(expected), "seed {seed}:\n{}", program.source.join("\n"));
+        compared += 1;
+    }
+    // most programs have to run without dividing by zero for the comparison to mean anything
+    assert!(compared > CASES / 2, "only {compared} of {CASES} programs ran without faulting");
+}
@@ -0,0 +1,385 @@
+// turns a `.sm` image back into source the assembler accepts.
+//
+// words reachable from the entry point are decoded as instructions, everything else becomes DC.
+// addresses used as operands get labels, `L<addr>` for branch targets and `D<addr>` for data, and
+// referenced addresses past the end of the image are reserved with DS so the source assembles
+// back to the same words. immediate operands stay numbers, indexed ones are labelled like direct ones
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fmt::Write;
+use std::str::FromStr;
+
+use assembler::{decode_instruction, decode_word, AddressingMode, Instruction, InstructionSet, OperandShape, WORD_SIZE};
+
+const MEMORY_SIZE: usize = 1000;
+
+// source text before the `; address word` comment
+const CODE_WIDTH: usize = 30;
+
+// the words a `.sm` file loads and where execution starts, as read by `SMAC0::parse_file`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub words: BTreeMap<usize, usize>,
+    pub entry: usize,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ImageError {
+    pub line_number: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "image line {}: {}", self.line_number, self.message)
+    }
+}
+
+impl std::error::Error for ImageError {}
+
+impl FromStr for Image {
+    type Err = ImageError;
+
+    // `ADDR WORD` lines and a final `-1 ENTRY`. like the simulator, a later word for the same
+    // address replaces the earlier one
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut words = BTreeMap::new();
+        let mut entry = None;
+
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let error = |message: &str| ImageError {
+                line_number,
+                message: message.to_string(),
+            };
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let address = |field: usize| -> Result<usize, ImageError> {
+                match fields.get(field).and_then(|text| text.parse::<usize>().ok()) {
+                    Some(address) if address < MEMORY_SIZE => Ok(address),
+                    Some(_) => Err(error("address is outside the 1000-word SMAC0 memory")),
+                    None => Err(error("expected an address")),
+                }
+            };
+
+            match fields.as_slice() {
+                [] => continue,
+                ["-1", ..] => entry = Some(address(1)?),
+                [_, word] => {
+                    let word = word.parse().map_err(|_| error("expected a word"))?;
+                    words.insert(address(0)?, word);
+                }
+                _ => return Err(error("expected `ADDR WORD` or `-1 ENTRY`")),
+            }
+        }
+
+        let entry = entry.ok_or(ImageError {
+            line_number: 0,
+            message: String::from("missing `-1 ENTRY` line"),
+        })?;
+        Ok(Image { words, entry })
+    }
+}
+
+// a word that reads as an instruction of the current set
+struct Decoded<'a> {
+    instruction: &'a Instruction,
+    mode: AddressingMode,
+    reg: usize,
+    mem: usize,
+}
+
+#[derive(Default)]
+pub struct Disassembler {
+    instruction_set: InstructionSet,
+}
+
+impl Disassembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // decode for a SMAC0 variant instead of the built-in instruction set
+    pub fn use_instruction_set(&mut self, instruction_set: InstructionSet) {
+        self.instruction_set = instruction_set;
+    }
+
+    pub fn disassemble(&self, image: &Image) -> String {
+        let code = self.reachable_code(image);
+        let labels = self.labels(image, &code);
+
+        let mut out = String::new();
+        let _ = writeln!(out, "{:8}START {}", "", image.entry);
+        let first = image.words.keys().next().copied().unwrap_or(image.entry);
+        // operands below the image have no place to live in it
+        for (address, label) in labels.range(..first) {
+            let _ = writeln!(out, "{label:8}EQU {address}");
+        }
+        if first != image.entry {
+            let _ = writeln!(out, "{:8}ORIGIN {first}", "");
+        }
+
+        let last_word = image.words.keys().next_back().map_or(first, |address| address + 1);
+        let last_label = labels.keys().next_back().map_or(first, |address| address + 1);
+        let end = last_word.max(last_label);
+        let mut address = first;
+        while address < end {
+            let label = labels.get(&address).map(|label| format!("{label}:")).unwrap_or_default();
+            if let Some(&word) = image.words.get(&address) {
+                // a word with a mode digit is too long to be data, so it is an instruction even when
+                // nothing reaches it
+                let as_code = code.contains(&address) || word >= WORD_SIZE;
+                let (text, size) = match as_code.then(|| self.decode(image, address, word)).flatten() {
+                    Some(decoded) => (self.source(&decoded, &labels), decoded.instruction.size),
+                    // data comes back as the signed value, so 999999 reads `DC -1`
+                    None if word < WORD_SIZE => (format!("DC {}", decode_word(word)), 1),
+                    // no source gives this word back, the assembler will say so
+                    None => (format!("DC {word}"), 1),
+                };
+                write_line(&mut out, &label, &text, &format!("{address:03} {word:06}"));
+                address += size;
+            } else {
+                // a run of empty words, broken at the next word or label
+                let run = (address + 1..end)
+                    .find(|next| image.words.contains_key(next) || labels.contains_key(next))
+                    .unwrap_or(end)
+                    - address;
+                write_line(&mut out, &label, &format!("DS {run}"), &format!("{address:03}"));
+                address += run;
+            }
+        }
+        let _ = writeln!(out, "{:8}END", "");
+        out
+    }
+
+    fn decode<'a>(&'a self, image: &Image, address: usize, word: usize) -> Option<Decoded<'a>> {
+        let (mode, opcode, reg, mem) = decode_instruction(word);
+        let instruction = self.instruction_set.instruction_with_opcode(opcode)?;
+        let mode_valid = match mode {
+            AddressingMode::Direct => true,
+            // only operands that are read can be immediate
+            AddressingMode::Immediate => {
+                matches!(instruction.shape, OperandShape::Memory | OperandShape::RegisterMemory)
+                    && !instruction.stores
+                    && !instruction.branches
+            }
+            AddressingMode::Indexed(index) => {
+                !matches!(instruction.shape, OperandShape::None | OperandShape::Register)
+                    && self.instruction_set.register_name(index).is_some()
+            }
+        };
+        let valid = match instruction.shape {
+            OperandShape::None => reg == 0 && mem == 0,
+            OperandShape::Register => mem == 0 && self.instruction_set.register_name(reg).is_some(),
+            OperandShape::Memory => reg == 0,
+            OperandShape::RegisterMemory => self.instruction_set.register_name(reg).is_some(),
+            OperandShape::ConditionMemory => self.instruction_set.condition_name(reg).is_some(),
+            OperandShape::Storage | OperandShape::Constant => false,
+        };
+        // the assembler leaves the extra words of a longer instruction empty
+        let fits = (address + 1..address + instruction.size).all(|next| !image.words.contains_key(&next));
+        (mode_valid && valid && fits).then_some(Decoded { instruction, mode, reg, mem })
+    }
+
+    // follows execution from the entry point. a none-operand instruction such as STOP or RET ends a
+    // path, and so does a branch on ANY. a CALL is followed into the subroutine and past it
+    fn reachable_code(&self, image: &Image) -> BTreeSet<usize> {
+        let mut code = BTreeSet::new();
+        let mut pending = vec![image.entry];
+        while let Some(address) = pending.pop() {
+            let Some(&word) = image.words.get(&address) else {
+                continue;
+            };
+            if code.contains(&address) {
+                continue;
+            }
+            let Some(decoded) = self.decode(image, address, word) else {
+                continue;
+            };
+            code.insert(address);
+
+            let falls_through = match decoded.instruction.shape {
+                OperandShape::None => false,
+                OperandShape::ConditionMemory => {
+                    // an indexed branch goes somewhere that depends on a register, it is not followed
+                    if decoded.mode == AddressingMode::Direct {
+                        pending.push(decoded.mem);
+                    }
+                    self.instruction_set.condition_name(decoded.reg) != Some("ANY")
+                }
+                _ => {
+                    if decoded.instruction.branches && decoded.mode == AddressingMode::Direct {
+                        pending.push(decoded.mem);
+                    }
+                    true
+                }
+            };
+            if falls_through {
+                pending.push(address + decoded.instruction.size);
+            }
+        }
+        code
+    }
+
+    fn labels(&self, image: &Image, code: &BTreeSet<usize>) -> BTreeMap<usize, String> {
+        let decoded: Vec<Decoded> = code
+            .iter()
+            .filter_map(|&address| self.decode(image, address, image.words[&address]))
+            .collect();
+
+        let addressed = decoded.iter().filter(|d| d.mode != AddressingMode::Immediate);
+        let mut labels = BTreeMap::new();
+        let branch = |d: &&Decoded| d.instruction.shape == OperandShape::ConditionMemory || d.instruction.branches;
+        for instruction in addressed.clone().filter(branch) {
+            labels.insert(instruction.mem, format!("L{:03}", instruction.mem));
+        }
+        for instruction in addressed.filter(|d| !matches!(d.instruction.shape, OperandShape::None | OperandShape::Register)) {
+            labels.entry(instruction.mem).or_insert_with(|| format!("D{:03}", instruction.mem));
+        }
+        labels
+    }
+
+    fn source(&self, decoded: &Decoded, labels: &BTreeMap<usize, String>) -> String {
+        let mnemonic = &decoded.instruction.mnemonic;
+        let target = || {
+            let address = labels.get(&decoded.mem).cloned().unwrap_or_else(|| decoded.mem.to_string());
+            match decoded.mode {
+                AddressingMode::Direct => address,
+                AddressingMode::Immediate => format!("#{}", decoded.mem),
+                AddressingMode::Indexed(index) => {
+                    format!("{address}({})", self.instruction_set.register_name(index).unwrap_or_default())
+                }
+            }
+        };
+        match decoded.instruction.shape {
+            OperandShape::Register => {
+                let register = self.instruction_set.register_name(decoded.reg).unwrap_or_default();
+                format!("{mnemonic} {register}")
+            }
+            OperandShape::RegisterMemory => {
+                let register = self.instruction_set.register_name(decoded.reg).unwrap_or_default();
+                format!("{mnemonic} {register} {}", target())
+            }
+            OperandShape::ConditionMemory => {
+                let condition = self.instruction_set.condition_name(decoded.reg).unwrap_or_default();
+                format!("{mnemonic} {condition} {}", target())
+            }
+            OperandShape::Memory => format!("{mnemonic} {}", target()),
+            _ => mnemonic.clone(),
+        }
+    }
+}
+
+fn write_line(out: &mut String, label: &str, text: &str, comment: &str) {
+    let code = format!("{label:8}{text}");
+    let _ = writeln!(out, "{code:CODE_WIDTH$}; {comment}");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assembler::Assembler;
+
+    const FACTORIAL: &str = include_str!("../../smac0_simulator/data/factorial.sm");
+    const SUM: &str = include_str!("../../smac0_simulator/data/sum.sm");
+
+    // assembles the disassembly and compares the words with the original image
+    fn assembles_back(image: &Image, source: &str) {
+        let lines: Vec<String> = source.lines().map(String::from).collect();
+        let output = Assembler::new().assemble(&lines);
+        assert!(output.error_table.is_empty(), "{}", output.error_table[0]);
+
+        let mut out = Vec::new();
+        output.write_machine_code(&mut out).unwrap();
+        let reassembled: Image = String::from_utf8(out).unwrap().parse().unwrap();
+        assert_eq!(&reassembled, image);
+    }
+
+    #[test]
+    fn disassembles_factorial() {
+        let image: Image = FACTORIAL.parse().unwrap();
+        let source = Disassembler::new().disassemble(&image);
+        let expected = "        START 100
+        READ D113             ; 100 090113
+        MOVER BREG D113       ; 101 041113
+        MOVER CREG D112       ; 102 042112
+L103:   COMP BREG D112        ; 103 061112
+        BC LE L109            ; 104 071109
+        MUL CREG D113         ; 105 032113
+        SUB BREG D112         ; 106 021112
+        MOVEM BREG D113       ; 107 051113
+        BC ANY L103           ; 108 075103
+L109:   MOVEM CREG D114       ; 109 052114
+        PRINT D114            ; 110 100114
+        STOP                  ; 111 000000
+D112:   DC 1                  ; 112 000001
+D113:   DS 1                  ; 113
+D114:   DS 1                  ; 114
+        END
+";
+        assert_eq!(source, expected);
+        assembles_back(&image, &source);
+    }
+
+    #[test]
+    fn disassembles_sum() {
+        let image: Image = SUM.parse().unwrap();
+        let source = Disassembler::new().disassemble(&image);
+        assert!(source.contains("        ADD BREG D108         ; 103 011108\n"));
+        assembles_back(&image, &source);
+    }
+
+    #[test]
+    fn unreachable_and_undecodable_words_are_data() {
+        let image: Image = "050 000007\n051 075053\n052 041050\n053 990000\n-1 051".parse().unwrap();
+        let source = Disassembler::new().disassemble(&image);
+        assert!(source.starts_with("        START 51\n        ORIGIN 50\n"));
+        assert!(source.contains("        DC 41050              ; 052 041050\n"));
+        assert!(source.contains("L053:   DC -10000             ; 053 990000\n"));
+        assembles_back(&image, &source);
+    }
+
+    #[test]
+    fn immediate_and_indexed_operands() {
+        let source = "START 100\nMOVER BREG #0\nL: ADD CREG A(BREG)\nADD BREG #1\nCOMP BREG #3\nBC LT L\nPRINT A\nSTOP\nA: DC 4\nEND";
+        let lines: Vec<String> = source.lines().map(String::from).collect();
+        let mut out = Vec::new();
+        Assembler::new().assemble(&lines).write_machine_code(&mut out).unwrap();
+        let image: Image = String::from_utf8(out).unwrap().parse().unwrap();
+
+        let source = Disassembler::new().disassemble(&image);
+        assert!(source.contains("        MOVER BREG #0         ; 100 1041000\n"));
+        assert!(source.contains("L101:   ADD CREG D107(BREG)   ; 101 3012107\n"));
+        assert!(source.contains("        COMP BREG #3          ; 103 1061003\n"));
+        assembles_back(&image, &source);
+
+        // immediate MOVEM has nothing to store to, and an unreachable word with a mode digit is still code
+        let image: Image = "100 1051005\n101 000000\n102 2011100\n-1 100".parse().unwrap();
+        let source = Disassembler::new().disassemble(&image);
+        assert!(source.contains("        DC 1051005            ; 100 1051005\n"));
+        assert!(source.contains("        ADD BREG 100(AREG)    ; 102 2011100\n"));
+    }
+
+    #[test]
+    fn subroutines_are_followed() {
+        let source = "START 100\nMOVER AREG #2\nCALL SQ\nSTOP\nSQ: PUSH AREG\nMOVEM AREG T\nPOP BREG\nMUL BREG T\nRET\nT: DS 1\nEND";
+        let lines: Vec<String> = source.lines().map(String::from).collect();
+        let mut out = Vec::new();
+        Assembler::new().assemble(&lines).write_machine_code(&mut out).unwrap();
+        let image: Image = String::from_utf8(out).unwrap().parse().unwrap();
+
+        let source = Disassembler::new().disassemble(&image);
+        assert!(source.contains("        CALL L103             ; 101 130103\n"));
+        assert!(source.contains("L103:   PUSH AREG             ; 103 150000\n"));
+        assert!(source.contains("        POP BREG              ; 105 161000\n"));
+        assert!(source.contains("        RET                   ; 107 140000\n"));
+        assembles_back(&image, &source);
+    }
+
+    #[test]
+    fn malformed_image_is_rejected() {
+        assert_eq!("100 000000\n".parse::<Image>().unwrap_err().line_number, 0);
+        assert_eq!("100 x\n-1 100".parse::<Image>().unwrap_err().line_number, 1);
+        assert!("1000 0\n-1 100".parse::<Image>().is_err());
+    }
+}
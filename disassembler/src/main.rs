@@ -0,0 +1,130 @@
+use std::env;
+use std::fs;
+use std::io::{self, Read};
+use std::process::ExitCode;
+
+use assembler::InstructionSet;
+use disassembler::{Disassembler, Image};
+
+const USAGE: &str = "usage: disassembler <image.sm | -> [-o out.asm] [--isa machine.isa]";
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    input: String,
+    output: Option<String>,
+    instruction_set: Option<String>,
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+    let mut input = None;
+    let mut options = Options {
+        input: String::new(),
+        output: None,
+        instruction_set: None,
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => {
+                let path = args.next().ok_or("-o needs an output file")?;
+                options.output = Some(path);
+            }
+            "--isa" => {
+                let path = args.next().ok_or("--isa needs an instruction set file")?;
+                options.instruction_set = Some(path);
+            }
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            // a lone `-` means stdin, anything else starting with `-` is a typo
+            flag if flag.starts_with('-') && flag != "-" => return Err(format!("unknown option `{flag}`")),
+            _ => {
+                if input.replace(arg).is_some() {
+                    return Err(String::from("only one image can be disassembled at a time"));
+                }
+            }
+        }
+    }
+
+    options.input = input.ok_or("no input file given")?;
+    Ok(options)
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{message}");
+            eprintln!("{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+
+    let mut disassembler = Disassembler::new();
+    if let Some(path) = &options.instruction_set {
+        match InstructionSet::from_file(path) {
+            Ok(instruction_set) => disassembler.use_instruction_set(instruction_set),
+            Err(message) => {
+                eprintln!("{path}: {message}");
+                return ExitCode::from(2);
+            }
+        }
+    }
+
+    let image = read_image(&options.input)
+        .map_err(|e| e.to_string())
+        .and_then(|text| text.parse::<Image>().map_err(|e| e.to_string()));
+    let image = match image {
+        Ok(image) => image,
+        Err(message) => {
+            eprintln!("{}: {message}", options.input);
+            return ExitCode::from(2);
+        }
+    };
+
+    let source = disassembler.disassemble(&image);
+    match &options.output {
+        Some(path) => {
+            if let Err(e) = fs::write(path, source) {
+                eprintln!("{path}: {e}");
+                return ExitCode::from(2);
+            }
+        }
+        None => print!("{source}"),
+    }
+    ExitCode::SUCCESS
+}
+
+fn read_image(input: &str) -> io::Result<String> {
+    if input == "-" {
+        let mut text = String::new();
+        io::stdin().read_to_string(&mut text)?;
+        Ok(text)
+    } else {
+        fs::read_to_string(input)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> impl Iterator<Item = String> {
+        line.split_whitespace().map(String::from).collect::<Vec<_>>().into_iter()
+    }
+
+    #[test]
+    fn parses_command_line() {
+        let options = parse_args(args("factorial.sm -o factorial.asm --isa big.isa")).unwrap();
+        assert_eq!(
+            options,
+            Options {
+                input: String::from("factorial.sm"),
+                output: Some(String::from("factorial.asm")),
+                instruction_set: Some(String::from("big.isa")),
+            }
+        );
+        assert_eq!(parse_args(args("-")).unwrap().input, "-");
+        assert!(parse_args(args("")).is_err());
+        assert!(parse_args(args("a.sm b.sm")).is_err());
+        assert!(parse_args(args("a.sm --bogus")).is_err());
+    }
+}
@@ -0,0 +1,277 @@
+// combines object modules written by `assembler --object` into one image for `smac0_simulator`.
+// modules are placed one after another starting at the load origin, every R record is relocated,
+// and every X record gets the final address of the ENTRY symbol it names, plus its offset
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+
+use assembler::{MachineCode, ObjectModule};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LinkError {
+    NoModules,
+    ModuleOutsideMemory { module: String, message: String },
+    DuplicateDefinition { name: String, first: String, second: String },
+    UnresolvedReference { name: String, module: String },
+    AddressOutOfRange { name: String, module: String, address: isize },
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkError::NoModules => write!(f, "nothing to link"),
+            LinkError::ModuleOutsideMemory { module, message } => write!(f, "{module}: {message}"),
+            LinkError::DuplicateDefinition { name, first, second } => {
+                write!(f, "`{name}` is defined in both {first} and {second}")
+            }
+            LinkError::UnresolvedReference { name, module } => {
+                write!(f, "{module}: undefined external symbol `{name}`")
+            }
+            LinkError::AddressOutOfRange { name, module, address } => {
+                write!(f, "{module}: `{name}` resolves to {address}, outside the 1000-word SMAC0 memory")
+            }
+        }
+    }
+}
+
+impl std::error::Error for LinkError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinkedImage {
+    pub words: Vec<MachineCode>,
+    pub entry: usize,
+}
+
+impl LinkedImage {
+    // same text format the assembler writes with `-o`
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        assembler::write_image(writer, &self.words, self.entry)
+    }
+}
+
+#[derive(Default)]
+pub struct Linker {
+    modules: Vec<ObjectModule>,
+}
+
+impl Linker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_module(&mut self, module: ObjectModule) {
+        self.modules.push(module);
+    }
+
+    // the program starts at the first module's entry point. all errors are collected, not just the first
+    pub fn link(&self, load_origin: usize) -> Result<LinkedImage, Vec<LinkError>> {
+        if self.modules.is_empty() {
+            return Err(vec![LinkError::NoModules]);
+        }
+        let mut errors = Vec::new();
+
+        // place the modules and relocate their own words
+        let mut images = Vec::with_capacity(self.modules.len());
+        let mut load_address = load_origin;
+        for module in &self.modules {
+            match module.relocate(load_address) {
+                Ok(words) => images.push((load_address, words)),
+                Err(message) => {
+                    errors.push(LinkError::ModuleOutsideMemory {
+                        module: module.name.clone(),
+                        message,
+                    });
+                    images.push((load_address, Vec::new()));
+                }
+            }
+            load_address += module.length;
+        }
+
+        // global symbol table of every ENTRY symbol at its final address
+        let mut globals: HashMap<&str, (usize, &str)> = HashMap::new();
+        for (module, (load_address, _)) in self.modules.iter().zip(&images) {
+            for definition in &module.definitions {
+                let Some(address) = module.relocated_definition(definition, *load_address) else {
+                    continue;
+                };
+                if let Some((_, first)) = globals.get(definition.name.as_str()) {
+                    errors.push(LinkError::DuplicateDefinition {
+                        name: definition.name.clone(),
+                        first: first.to_string(),
+                        second: module.name.clone(),
+                    });
+                } else {
+                    globals.insert(&definition.name, (address, &module.name));
+                }
+            }
+        }
+
+        // the symbol's address plus the reference's offset goes in the mem field
+        for (module, (_, words)) in self.modules.iter().zip(images.iter_mut()) {
+            for reference in &module.external_references {
+                let Some((address, _)) = globals.get(reference.name.as_str()) else {
+                    errors.push(LinkError::UnresolvedReference {
+                        name: reference.name.clone(),
+                        module: module.name.clone(),
+                    });
+                    continue;
+                };
+                let Some(index) = module.text.iter().position(|entry| entry.address == reference.address) else {
+                    continue;
+                };
+                let Some(word) = words.get_mut(index) else {
+                    continue;
+                };
+                let mem = (*address as isize).saturating_add(reference.offset);
+                if !(0..1000).contains(&mem) {
+                    errors.push(LinkError::AddressOutOfRange {
+                        name: reference.name.clone(),
+                        module: module.name.clone(),
+                        address: mem,
+                    });
+                    continue;
+                }
+                word.word = word.word - word.word % 1000 + mem as usize;
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        let first = &self.modules[0];
+        Ok(LinkedImage {
+            words: images.into_iter().flat_map(|(_, words)| words).collect(),
+            entry: first.relocated_entry(load_origin).unwrap_or(load_origin),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assembler::Assembler;
+
+    fn assemble(file_name: &str, source: &[&str]) -> ObjectModule {
+        let output = Assembler::with_file_name(file_name).assemble(source);
+        assert!(output.error_table.is_empty(), "{:?}", output.error_table);
+        output.object_module()
+    }
+
+    fn words(image: &LinkedImage) -> Vec<(usize, usize)> {
+        image.words.iter().map(|m| (m.address, m.word)).collect()
+    }
+
+    #[test]
+    fn resolves_externals_across_modules() {
+        let main = assemble(
+            "main.asm",
+            &["START 0", "EXTRN ADDONE", "EXTRN N", "READ N", "BC ANY ADDONE", "END"],
+        );
+        let lib = assemble(
+            "lib.asm",
+            &["START 0", "ENTRY ADDONE", "ENTRY N", "ADDONE: ADD AREG ='1'", "STOP", "N: DS 1", "END"],
+        );
+
+        let mut linker = Linker::new();
+        linker.add_module(main);
+        linker.add_module(lib);
+        let image = linker.link(100).unwrap();
+        // main sits at 100-101, lib at 102-105 with its literal last
+        assert_eq!(
+            words(&image),
+            vec![(100, 90104), (101, 75102), (102, 10105), (103, 0), (105, 1)]
+        );
+        assert_eq!(image.entry, 100);
+
+        let mut out = Vec::new();
+        image.write(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "100 090104\n101 075102\n102 010105\n103 000000\n105 000001\n-1 100"
+        );
+    }
+
+    #[test]
+    fn absolute_definitions_are_not_relocated() {
+        let main = assemble(
+            "main.asm",
+            &["START 0", "EXTRN SIZE", "MOVER AREG #SIZE", "STOP", "END"],
+        );
+        let sizes = assemble("sizes.asm", &["START 0", "SIZE EQU 5", "ENTRY SIZE", "STOP", "END"]);
+        assert_eq!(sizes.to_string(), "H SIZES 000 001\nT 000 000000\nD SIZE 005 A\nE 000\n");
+
+        let mut linker = Linker::new();
+        linker.add_module(main);
+        linker.add_module(sizes);
+        let image = linker.link(200).unwrap();
+        assert_eq!(words(&image), vec![(200, 1040005), (201, 0), (202, 0)]);
+    }
+
+    #[test]
+    fn reports_unresolved_and_duplicate_symbols() {
+        let a = assemble("a.asm", &["ENTRY X", "EXTRN MISSING", "X: READ MISSING", "END"]);
+        let b = assemble("b.asm", &["ENTRY X", "X: STOP", "END"]);
+
+        let mut linker = Linker::new();
+        linker.add_module(a);
+        linker.add_module(b);
+        let errors = linker.link(0).unwrap_err();
+        assert_eq!(
+            errors,
+            vec![
+                LinkError::DuplicateDefinition {
+                    name: String::from("X"),
+                    first: String::from("A"),
+                    second: String::from("B"),
+                },
+                LinkError::UnresolvedReference {
+                    name: String::from("MISSING"),
+                    module: String::from("A"),
+                },
+            ]
+        );
+        assert_eq!(Linker::new().link(0).unwrap_err(), vec![LinkError::NoModules]);
+    }
+
+    #[test]
+    fn links_units_assembled_in_a_shared_namespace() {
+        let mut assembler = Assembler::new();
+        assembler.share_namespace();
+        assembler.set_file_name("a.asm");
+        let a = assembler.assemble(["START 0", "SIZE EQU 3", "X: DC 7", "END"]);
+        assembler.set_file_name("b.asm");
+        let b = assembler.assemble(["START 0", "PRINT X", "MOVER AREG #SIZE", "STOP", "END"]);
+        assert!(a.error_table.is_empty() && b.error_table.is_empty());
+
+        let mut linker = Linker::new();
+        linker.add_module(a.object_module());
+        linker.add_module(b.object_module());
+        let image = linker.link(100).unwrap();
+        assert_eq!(words(&image), vec![(100, 7), (101, 100100), (102, 1040003), (103, 0)]);
+    }
+
+    #[test]
+    fn checks_external_offsets_against_memory() {
+        let main = assemble("main.asm", &["START 0", "EXTRN TABLE", "READ TABLE-1", "STOP", "END"]);
+        let table = assemble("table.asm", &["START 0", "ENTRY TABLE", "TABLE: DS 2", "END"]);
+
+        let mut linker = Linker::new();
+        linker.add_module(main.clone());
+        linker.add_module(table.clone());
+        let image = linker.link(100).unwrap();
+        assert_eq!(words(&image), vec![(100, 90101), (101, 0)]);
+
+        let mut linker = Linker::new();
+        linker.add_module(table);
+        linker.add_module(main);
+        assert_eq!(
+            linker.link(0).unwrap_err(),
+            vec![LinkError::AddressOutOfRange {
+                name: String::from("TABLE"),
+                module: String::from("MAIN"),
+                address: -1,
+            }]
+        );
+    }
+}
@@ -0,0 +1,118 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::process::ExitCode;
+
+use assembler::ObjectModule;
+use linker::Linker;
+
+const USAGE: &str = "usage: linker <module.obj>... [-o out.sm] [--origin N]";
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    inputs: Vec<String>,
+    output: Option<String>,
+    origin: usize,
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+    let mut options = Options {
+        inputs: Vec::new(),
+        output: None,
+        origin: 0,
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => {
+                let path = args.next().ok_or("-o needs an output file")?;
+                options.output = Some(path);
+            }
+            "--origin" => {
+                let origin = args.next().ok_or("--origin needs an address")?;
+                options.origin = origin.parse().map_err(|_| format!("invalid origin `{origin}`"))?;
+            }
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            flag if flag.starts_with('-') => return Err(format!("unknown option `{flag}`")),
+            _ => options.inputs.push(arg),
+        }
+    }
+
+    if options.inputs.is_empty() {
+        return Err(String::from("no object modules given"));
+    }
+    Ok(options)
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{message}");
+            eprintln!("{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+
+    let mut linker = Linker::new();
+    for input in &options.inputs {
+        let module = fs::read_to_string(input)
+            .map_err(|e| e.to_string())
+            .and_then(|text| text.parse::<ObjectModule>().map_err(|e| e.to_string()));
+        match module {
+            Ok(module) => linker.add_module(module),
+            Err(message) => {
+                eprintln!("{input}: {message}");
+                return ExitCode::from(2);
+            }
+        }
+    }
+
+    let image = match linker.link(options.origin) {
+        Ok(image) => image,
+        Err(errors) => {
+            for error in errors {
+                eprintln!("error: {error}");
+            }
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let written = match &options.output {
+        Some(path) => File::create(path).and_then(|mut file| image.write(&mut file)),
+        None => {
+            let mut stdout = io::stdout().lock();
+            image.write(&mut stdout).and_then(|_| writeln!(stdout))
+        }
+    };
+    if let Err(e) = written {
+        eprintln!("{}: {e}", options.output.as_deref().unwrap_or("<stdout>"));
+        return ExitCode::from(2);
+    }
+    ExitCode::SUCCESS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> impl Iterator<Item = String> {
+        list.iter().map(|s| s.to_string()).collect::<Vec<_>>().into_iter()
+    }
+
+    #[test]
+    fn parses_command_line() {
+        let options = parse_args(args(&["main.obj", "lib.obj", "-o", "prog.sm", "--origin", "100"])).unwrap();
+        assert_eq!(
+            options,
+            Options {
+                inputs: vec![String::from("main.obj"), String::from("lib.obj")],
+                output: Some(String::from("prog.sm")),
+                origin: 100,
+            }
+        );
+        assert!(parse_args(args(&[])).is_err());
+        assert!(parse_args(args(&["a.obj", "--origin", "x"])).is_err());
+        assert!(parse_args(args(&["a.obj", "--verbose"])).is_err());
+    }
+}
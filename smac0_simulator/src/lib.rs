@@ -1,26 +1,134 @@
-#[warn(unused_assignments)]
-
+use std::fmt;
 use std::fs;
-use std::io::{self, Write};
+use std::io::{self, BufRead, Write};
+use std::ops::Range;
 use std::process;
 
+// a word is six decimal digits holding -500000 through 499999, negative values in ten's complement
+// so -1 is stored as 999999. memory keeps the stored words, registers the values they stand for.
+// instructions that don't use direct addressing carry a seventh digit for the mode
+const WORD_SIZE: isize = 1_000_000;
+const WORD_MIN: isize = -WORD_SIZE / 2;
+const WORD_MAX: isize = WORD_SIZE / 2 - 1;
+
+fn signed(word: usize) -> isize {
+    wrap(word as isize)
+}
+
+fn stored(value: isize) -> usize {
+    value.rem_euclid(WORD_SIZE) as usize
+}
+
+// arithmetic overflows wrap around within a word, like an odometer
+fn wrap(value: isize) -> isize {
+    (value - WORD_MIN).rem_euclid(WORD_SIZE) + WORD_MIN
+}
+
+// the last 100 words unless `set_stack_region` says otherwise, out of the way of programs that START
+// low in memory
+const DEFAULT_STACK: Range<usize> = 900..1000;
+
+// faults the machine raises itself, as opposed to a program that can't be decoded. `address` is
+// where the faulting instruction is
+#[derive(Debug, PartialEq)]
+pub enum Fault {
+    // PUSH or CALL with every word of the stack region in use
+    StackOverflow { address: usize },
+    // POP or RET with nothing on the stack
+    StackUnderflow { address: usize },
+    // PUSH or CALL onto a word the program was loaded into, at `target`
+    StackCollision { address: usize, target: usize },
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fault::StackOverflow { address } => write!(f, "stack overflow at {address:03}"),
+            Fault::StackUnderflow { address } => write!(f, "stack underflow at {address:03}"),
+            Fault::StackCollision { address, target } => {
+                write!(f, "stack at {address:03} would overwrite the program at {target:03}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Fault {}
+
 pub struct SMAC0 {
     memory: [usize; 1000],
-    registers: [usize; 4],
+    // words the image gave a value, the stack may not overwrite them
+    loaded: [bool; 1000],
+    registers: [isize; 4],
     condition_codes: [bool; 6],
     program_counter: usize,
     last_logical_addr: usize,
+    // the stack grows down from the end of its region, the stack pointer is the address of the
+    // word on top, or the end of the region when the stack is empty
+    stack: Range<usize>,
+    stack_pointer: usize,
+    // where READ takes its numbers from and PRINT and trace write to, stdin and stdout by default
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+}
+
+impl Default for SMAC0 {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl SMAC0 {
     pub fn new() -> Self{
+        Self::with_io(Box::new(io::BufReader::new(io::stdin())), Box::new(io::stdout()))
+    }
+
+    pub fn with_io(input: Box<dyn BufRead>, output: Box<dyn Write>) -> Self {
         Self {
             memory: [0; 1000],
+            loaded: [false; 1000],
             registers: [0; 4],
             condition_codes: [false; 6],
             program_counter: 0,
             last_logical_addr: 0,
+            stack: DEFAULT_STACK,
+            stack_pointer: DEFAULT_STACK.end,
+            input,
+            output,
+        }
+    }
+
+    // the words PUSH and CALL may use, this empties the stack. a program loaded later into the
+    // region is caught by `push` instead
+    pub fn set_stack_region(&mut self, region: Range<usize>) -> Result<(), String> {
+        if region.is_empty() || region.end > self.memory.len() {
+            return Err(format!("stack region {}..{} is not inside memory", region.start, region.end));
+        }
+        if let Some(address) = region.clone().find(|&address| self.loaded[address]) {
+            return Err(format!("stack region {}..{} overlaps the program at {address:03}", region.start, region.end));
+        }
+        self.stack_pointer = region.end;
+        self.stack = region;
+        Ok(())
+    }
+
+    fn push(&mut self, word: usize) -> Result<(), Fault> {
+        if self.stack_pointer == self.stack.start {
+            return Err(Fault::StackOverflow { address: self.program_counter });
+        }
+        if self.loaded[self.stack_pointer - 1] {
+            return Err(Fault::StackCollision { address: self.program_counter, target: self.stack_pointer - 1 });
         }
+        self.stack_pointer -= 1;
+        self.memory[self.stack_pointer] = word;
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<usize, Fault> {
+        if self.stack_pointer == self.stack.end {
+            return Err(Fault::StackUnderflow { address: self.program_counter });
+        }
+        self.stack_pointer += 1;
+        Ok(self.memory[self.stack_pointer - 1])
     }
 
     pub fn process_input() -> Result<String, io::Error> {
@@ -40,14 +148,16 @@ impl SMAC0 {
                 self.program_counter = line[3..=5].parse::<usize>().unwrap();
             } else {
                 let addr = line[..=2].parse::<usize>().unwrap();
-                self.last_logical_addr = addr;
+                // words need not arrive in address order
+                self.last_logical_addr = self.last_logical_addr.max(addr);
                 self.memory[addr] = line[4..].parse::<usize>().unwrap();
+                self.loaded[addr] = true;
             }
         }
     }
 
     pub fn load_program(&mut self, filename: &str) {
-        let contents = fs::read_to_string("data/"filename)
+        let contents = fs::read_to_string(format!("data/{filename}"))
             .expect("should have been able to read the file");
         self.parse_file(contents);
     }
@@ -59,49 +169,82 @@ impl SMAC0 {
     }
 
     pub fn execute_line(&mut self) -> Result<&'static str, Box<dyn std::error::Error>> {
-        let mem_str = self.memory[self.program_counter].to_string();
-        let (mut opcode, mut register_op, mut mem_op) = (0, 0, 0);
-
-        if mem_str.len() == 1 {
-            return Ok("break");
+        // MODE-OPCODE-REG-MEM, decoded by value so leading zeros don't matter. the mode digit is 0 for
+        // direct addressing, 1 for immediate and 2 plus the index register for indexed
+        let word = self.memory[self.program_counter];
+        let (mode, opcode, register_op) = (word / 1_000_000, word / 10000 % 100, word / 1000 % 10);
+        if matches!(opcode, 1..=6 | 8 | 15 | 16) && register_op >= self.registers.len() {
+            return Err("invalid register".into());
         }
 
-        if mem_str.len() == 6 {
-            (opcode, register_op, mem_op) = ((&mem_str[..=1]).parse::<usize>()?, (&mem_str[2..=2]).parse::<usize>()?, (&mem_str[3..=5]).parse::<usize>()?);
-        } else {
-            (opcode, register_op, mem_op) = ((&mem_str[..=0]).parse::<usize>()?, (&mem_str[1..=1]).parse::<usize>()?, (&mem_str[2..=4]).parse::<usize>()?);
+        let mem_op = match mode {
+            0 | 1 => word % 1000,
+            _ => {
+                let index = self.registers.get(mode - 2).ok_or("invalid index register")?;
+                let address = (word % 1000) as isize + index;
+                if !(0..self.memory.len() as isize).contains(&address) {
+                    return Err(format!("indexed address {address} is outside memory").into());
+                }
+                address as usize
+            }
+        };
+        // an immediate operand is the mem field itself, there is nothing to store to or branch to
+        let immediate = mode == 1;
+        if immediate && matches!(opcode, 5 | 7 | 9 | 13) {
+            return Err("immediate operand where an address is needed".into());
         }
-
+        let operand = if immediate { mem_op as isize } else { signed(self.memory[mem_op]) };
         match opcode {
             0 => return Ok("break"),
-            1 => self.registers[register_op] += self.memory[mem_op],
-            2 => self.registers[register_op] -= self.memory[mem_op],
-            3 => self.registers[register_op] *= self.memory[mem_op],
-            8 => self.registers[register_op] /= self.memory[mem_op],
-            4 => self.registers[register_op] = self.memory[mem_op],
-            5 => self.memory[mem_op] = self.registers[register_op],
+            1 => self.registers[register_op] = wrap(self.registers[register_op] + operand),
+            2 => self.registers[register_op] = wrap(self.registers[register_op] - operand),
+            3 => self.registers[register_op] = wrap(self.registers[register_op] * operand),
+            8 => {
+                if operand == 0 {
+                    return Err("division by zero".into());
+                }
+                self.registers[register_op] = wrap(self.registers[register_op] / operand);
+            },
+            4 => self.registers[register_op] = operand,
+            5 => self.memory[mem_op] = stored(self.registers[register_op]),
             6 => {
-                self.condition_codes[0] = self.registers[register_op] <  self.memory[mem_op];
-                self.condition_codes[1] = self.registers[register_op] <= self.memory[mem_op];
-                self.condition_codes[2] = self.registers[register_op] == self.memory[mem_op];
-                self.condition_codes[3] = self.registers[register_op] >  self.memory[mem_op];
-                self.condition_codes[4] = self.registers[register_op] >= self.memory[mem_op];
+                self.condition_codes[0] = self.registers[register_op] <  operand;
+                self.condition_codes[1] = self.registers[register_op] <= operand;
+                self.condition_codes[2] = self.registers[register_op] == operand;
+                self.condition_codes[3] = self.registers[register_op] >  operand;
+                self.condition_codes[4] = self.registers[register_op] >= operand;
                 self.condition_codes[5] = true;
             },
             7 => {
-                if self.condition_codes[register_op] || register_op == 5 {
+                if self.condition_codes.get(register_op) == Some(&true) || register_op == 5 {
                     self.program_counter = mem_op;
                     return Ok("continue");
                 }
             },
             9 => {
-                println!("taking input for mem block {mem_op}:");
+                writeln!(self.output, "taking input for mem block {mem_op}:")?;
+                self.output.flush()?;
                 let mut input = String::new();
-                io::stdin().read_line(&mut input)?;
-                let input_int = input.trim().parse::<usize>()?;
-                self.memory[mem_op] = input_int;
+                self.input.read_line(&mut input)?;
+                let input_int = input.trim().parse::<isize>()?;
+                if !(WORD_MIN..=WORD_MAX).contains(&input_int) {
+                    return Err(format!("{input_int} does not fit in a word").into());
+                }
+                self.memory[mem_op] = stored(input_int);
             },
-            10 => println!("printing: {}", self.memory[mem_op]),
+            10 => writeln!(self.output, "printing: {operand}")?,
+            // the return address goes on the stack
+            13 => {
+                self.push(self.program_counter + 1)?;
+                self.program_counter = mem_op;
+                return Ok("continue");
+            },
+            14 => {
+                self.program_counter = self.pop()?;
+                return Ok("continue");
+            },
+            15 => self.push(stored(self.registers[register_op]))?,
+            16 => self.registers[register_op] = signed(self.pop()?),
             _ => return Err("invalid opcode".into())
         }
         self.program_counter += 1;
@@ -109,7 +252,7 @@ impl SMAC0 {
     }
 
     pub fn execute(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        while self.program_counter < self.last_logical_addr {
+        while self.program_counter <= self.last_logical_addr {
             match self.execute_line()? {
                 "full cycle done" | "continue" => {},
                 "break" => break,
@@ -120,9 +263,9 @@ impl SMAC0 {
     }
 
     pub fn trace(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        println!("program_counter: {}, last_logical_addr: {}", self.program_counter, self.last_logical_addr);
-        while self.program_counter < self.last_logical_addr {
-            println!("program_counter: {}, registers: {:?}, condition codes: {:?}", self.program_counter, self.registers, self.condition_codes);
+        writeln!(self.output, "program_counter: {}, last_logical_addr: {}", self.program_counter, self.last_logical_addr)?;
+        while self.program_counter <= self.last_logical_addr {
+            writeln!(self.output, "program_counter: {}, registers: {:?}, condition codes: {:?}, stack_pointer: {}", self.program_counter, self.registers, self.condition_codes, self.stack_pointer)?;
             match self.execute_line()? {
                 "full cycle done" | "continue" => {},
                 "break" => break,
@@ -152,6 +295,13 @@ impl SMAC0 {
                         eprintln!("Filename not provided.");
                     }
                 },
+                Some("stack") => {
+                    let bounds: Vec<usize> = args.filter_map(|arg| arg.parse().ok()).collect();
+                    let result = stack_region(&bounds).and_then(|region| self.set_stack_region(region));
+                    if let Err(e) = result {
+                        eprintln!("{e}");
+                    }
+                },
                 Some("print") => {
                     self.print_loaded_program();
                 },
@@ -171,4 +321,117 @@ impl SMAC0 {
         }
     }
 
-}
\ No newline at end of file
+}
+
+// `stack 900 999` in the REPL, first and last word of the region
+fn stack_region(bounds: &[usize]) -> Result<Range<usize>, String> {
+    match bounds {
+        [first, last] => last
+            .checked_add(1)
+            .map(|end| *first..end)
+            .ok_or_else(|| format!("stack region ending at {last} is not inside memory")),
+        _ => Err(String::from("usage: stack <first> <last>")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(image: &str) -> (SMAC0, Result<(), Box<dyn std::error::Error>>) {
+        let mut smac = SMAC0::with_io(Box::new(io::empty()), Box::new(io::sink()));
+        smac.parse_file(image.to_string());
+        let result = smac.execute();
+        (smac, result)
+    }
+
+    #[test]
+    fn loads_programs_from_the_data_directory() {
+        let mut smac = SMAC0::new();
+        smac.load_program("sum.sm");
+        assert_eq!(smac.program_counter, 100);
+        assert_eq!(smac.last_logical_addr, 106);
+        assert_eq!(smac.memory[102], 41107);
+    }
+
+    #[test]
+    fn words_are_decoded_by_value() {
+        // STOP with a stray mem field, its text is too short for fixed digit positions
+        let (smac, result) = run("100 000105\n101 041102\n102 000007\n-1 100");
+        assert!(result.is_ok());
+        assert_eq!(smac.program_counter, 100);
+        assert_eq!(smac.registers[1], 0);
+
+        // a register or condition code the machine doesn't have is an error rather than a panic
+        assert!(run("100 047102\n101 000000\n-1 100").1.is_err());
+        let (smac, result) = run("100 079101\n101 000000\n-1 100");
+        assert!(result.is_ok());
+        assert_eq!(smac.program_counter, 101);
+    }
+
+    #[test]
+    fn the_last_word_is_executed() {
+        // MOVER BREG 100 at the highest address
+        let (smac, result) = run("100 000007\n101 041100\n-1 101");
+        assert!(result.is_ok());
+        assert_eq!(smac.registers[1], 7);
+    }
+
+    #[test]
+    fn words_may_arrive_out_of_order() {
+        let (smac, result) = run("101 041100\n100 000007\n-1 101");
+        assert!(result.is_ok());
+        assert_eq!(smac.last_logical_addr, 101);
+        assert_eq!(smac.registers[1], 7);
+    }
+
+    #[test]
+    fn call_returns_and_stack_round_trips() {
+        // MOVER AREG #5, CALL 200, STOP; 200: PUSH AREG, POP BREG, RET
+        let (smac, result) = run("100 1040005\n101 130200\n102 000000\n200 150000\n201 161000\n202 140000\n-1 100");
+        assert!(result.is_ok());
+        assert_eq!(smac.registers[1], 5);
+        assert_eq!(smac.stack_pointer, DEFAULT_STACK.end);
+        // the return address and the pushed value are left behind in the region
+        assert_eq!((smac.memory[999], smac.memory[998]), (102, 5));
+    }
+
+    #[test]
+    fn stack_faults() {
+        let fault = |result: Result<(), Box<dyn std::error::Error>>| result.unwrap_err().downcast::<Fault>().ok().map(|f| *f);
+
+        // a CALL to itself recurses until the stack runs out
+        let mut smac = SMAC0::with_io(Box::new(io::empty()), Box::new(io::sink()));
+        smac.set_stack_region(990..992).unwrap();
+        smac.parse_file(String::from("100 130100\n-1 100"));
+        assert_eq!(fault(smac.execute()), Some(Fault::StackOverflow { address: 100 }));
+        assert_eq!(smac.stack_pointer, 990);
+
+        let (_, result) = run("100 140000\n-1 100");
+        assert_eq!(fault(result), Some(Fault::StackUnderflow { address: 100 }));
+        let (_, result) = run("100 162000\n-1 100");
+        assert_eq!(fault(result), Some(Fault::StackUnderflow { address: 100 }));
+
+        assert!(smac.set_stack_region(995..1001).is_err());
+        assert!(smac.set_stack_region(500..500).is_err());
+        assert!(stack_region(&[0, usize::MAX]).is_err());
+        assert_eq!(stack_region(&[900, 999]), Ok(900..1000));
+    }
+
+    #[test]
+    fn the_stack_does_not_overwrite_the_program() {
+        // PUSH AREG with the program sitting in the default region
+        let (smac, result) = run("998 150000
+999 000000
+-1 998");
+        let fault = result.unwrap_err().downcast::<Fault>().ok().map(|f| *f);
+        assert_eq!(fault, Some(Fault::StackCollision { address: 998, target: 999 }));
+        assert_eq!(smac.memory[999], 0);
+
+        let mut smac = SMAC0::with_io(Box::new(io::empty()), Box::new(io::sink()));
+        smac.parse_file(String::from("100 000000
+-1 100"));
+        assert!(smac.set_stack_region(100..110).is_err());
+        assert!(smac.set_stack_region(101..110).is_ok());
+    }
+}